@@ -0,0 +1,69 @@
+use base64::{engine::general_purpose, Engine as _};
+
+/// The `WWW-Authenticate` challenge of a 401, telling clients to send the
+/// credentials as UTF-8 rather than guessing an encoding.
+pub const CHALLENGE: &str = "Basic realm=\"webdav\", charset=\"UTF-8\"";
+
+/// [Basic authentication](https://www.rfc-editor.org/rfc/rfc7617)
+///
+/// The user-id and password of an `Authorization: Basic` header, decoded as
+/// UTF-8. The user-id ends at the first `:`; the password may contain more.
+/// The scheme is matched case-insensitively and any run of whitespace may
+/// separate it from the credentials.
+pub fn basic_credentials(header: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = header.trim().split_once(char::is_whitespace)?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenge_asks_for_utf8() {
+        assert!(CHALLENGE.starts_with("Basic realm="));
+        assert!(CHALLENGE.ends_with(", charset=\"UTF-8\""));
+    }
+
+    #[test]
+    fn decodes_utf8_credentials() {
+        assert_eq!(
+            basic_credentials("Basic asO2cmc6cMOkJCR3w7ZyZA=="),
+            Some(("jörg".to_string(), "pä$$wörd".to_string()))
+        );
+        assert_eq!(
+            basic_credentials("Basic dXNlcjpwYTpzcw=="),
+            Some(("user".to_string(), "pa:ss".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_credentials_that_are_not_utf8() {
+        assert_eq!(basic_credentials("Basic /zp4"), None);
+    }
+
+    #[test]
+    fn tolerates_whitespace_and_scheme_case() {
+        let expected = Some(("user".to_string(), "pa:ss".to_string()));
+        assert_eq!(basic_credentials("basic   dXNlcjpwYTpzcw=="), expected);
+        assert_eq!(basic_credentials("  BASIC\tdXNlcjpwYTpzcw==  "), expected);
+        assert_eq!(
+            basic_credentials("Basic   Og=="),
+            Some((String::new(), String::new()))
+        );
+    }
+
+    #[test]
+    fn rejects_other_schemes_and_bad_encodings() {
+        assert_eq!(basic_credentials("Bearer dXNlcjpwYTpzcw=="), None);
+        assert_eq!(basic_credentials("Basic"), None);
+        assert_eq!(basic_credentials("Basic !!!"), None);
+        assert_eq!(basic_credentials("Basic bm9jb2xvbg=="), None);
+    }
+}
@@ -0,0 +1,582 @@
+use serde_json::{json, Value};
+use std::str::FromStr;
+use worker::Env;
+
+use crate::constant::CUSTOM_NAMESPACE;
+use crate::path::normalize_key;
+
+/// Name of the R2 bucket binding in `wrangler.toml`.
+pub const BUCKET_BINDING: &str = "bucket";
+/// Keys the worker writes for its own bookkeeping live under this prefix.
+pub const INTERNAL_PREFIX: &str = ".dav";
+/// Diagnostics path answered by GET instead of an object.
+pub const INFO_KEY: &str = ".dav/info";
+/// POST target taking a JSON array of keys to delete in one request.
+pub const BULK_DELETE_KEY: &str = ".dav/bulk-delete";
+/// POST target copying objects in from an S3 bucket.
+pub const IMPORT_KEY: &str = ".dav/import";
+/// GET target minting share links when `SIGNING_SECRET` is set.
+pub const SIGN_KEY: &str = ".dav/sign";
+/// Soft-deleted objects are kept below `.trash/{deleted at, ms}/`.
+pub const TRASH_PREFIX: &str = ".trash";
+/// Overwritten objects are kept as `.versions/{key}/{replaced at, ms}`.
+pub const VERSIONS_PREFIX: &str = ".versions";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    hidden_prefixes: Vec<String>,
+    allowed_origins: Option<Vec<String>>,
+    pub sniff_content_type: bool,
+    pub filter_apple_double: bool,
+    pub max_propfind_entries: usize,
+    pub max_listed_objects: usize,
+    pub propfind_time_budget_ms: u64,
+    pub edge_cache: bool,
+    pub edge_cache_ttl: u64,
+    pub request_timeout_ms: u64,
+    pub soft_delete: bool,
+    pub trash_retention_days: u64,
+    pub versioning: bool,
+    pub bind: bool,
+    pub origin_fallback_url: Option<String>,
+    pub origin_fallback_store: bool,
+    pub cors_max_age: u64,
+    pub cors_allow_credentials: bool,
+    pub list_page_size: u32,
+    pub public_assets: bool,
+    pub robots_txt: String,
+    pub index_document: Option<String>,
+    allowed_extensions: Vec<String>,
+    blocked_extensions: Vec<String>,
+    pub retry_attempts: u32,
+    pub serve_precompressed: bool,
+    pub put_slash_as_mkcol: bool,
+    pub canonical_host: Option<String>,
+    pub s3_import: bool,
+    write_only_prefixes: Vec<String>,
+    pub signing_secret: Option<String>,
+    /// `ADMIN_USERNAME` and `ADMIN_PASSWORD`, the only credentials that may
+    /// run the bucket-wide operations.
+    admin: Option<(String, String)>,
+    pub listing_template: Option<String>,
+    pub listing_template_key: Option<String>,
+    pub root_usage: bool,
+    /// `/dav` when routed at `example.com/dav/*`, empty at the root.
+    pub mount_path: String,
+    /// Namespace of `tags` and the other properties outside `DAV:`.
+    pub custom_namespace: String,
+}
+
+impl Config {
+    pub fn from_env(env: &Env) -> Self {
+        Self::from_vars(|name| env.var(name).ok().map(|v| v.to_string()))
+    }
+
+    /// The settings as `var` looks them up by name, which lets tests stand a
+    /// map in for the env.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let var = &var;
+        let mut hidden_prefixes = vec![
+            INTERNAL_PREFIX.to_string(),
+            TRASH_PREFIX.to_string(),
+            VERSIONS_PREFIX.to_string(),
+        ];
+        hidden_prefixes.extend(key_list_var(var, "HIDDEN_PREFIXES"));
+        Self {
+            hidden_prefixes,
+            allowed_origins: var("ALLOWED_ORIGINS")
+                .is_some()
+                .then(|| list_var(var, "ALLOWED_ORIGINS")),
+            sniff_content_type: flag_var(var, "SNIFF_CONTENT_TYPE"),
+            filter_apple_double: flag_var(var, "FILTER_APPLE_DOUBLE"),
+            max_propfind_entries: number_var(var, "MAX_PROPFIND_ENTRIES", 5000),
+            max_listed_objects: number_var(var, "MAX_LISTED_OBJECTS", 100_000),
+            propfind_time_budget_ms: number_var(var, "PROPFIND_TIME_BUDGET_MS", 20_000),
+            edge_cache: flag_var(var, "ENABLE_EDGE_CACHE"),
+            edge_cache_ttl: number_var(var, "EDGE_CACHE_TTL", 3600),
+            request_timeout_ms: number_var(var, "REQUEST_TIMEOUT_MS", 0),
+            soft_delete: flag_var(var, "SOFT_DELETE"),
+            trash_retention_days: number_var(var, "TRASH_RETENTION_DAYS", 30),
+            versioning: flag_var(var, "VERSIONING"),
+            // on unless turned off explicitly
+            bind: var("ENABLE_BIND").is_none() || flag_var(var, "ENABLE_BIND"),
+            origin_fallback_url: var("ORIGIN_FALLBACK_URL")
+                .map(|v| v.trim().trim_end_matches('/').to_string())
+                .filter(|v| !v.is_empty()),
+            origin_fallback_store: flag_var(var, "ORIGIN_FALLBACK_STORE"),
+            cors_max_age: number_var(var, "CORS_MAX_AGE", 86400),
+            cors_allow_credentials: flag_var(var, "CORS_ALLOW_CREDENTIALS"),
+            list_page_size: number_var(var, "LIST_PAGE_SIZE", 1000u32).clamp(1, 1000),
+            // on unless turned off explicitly
+            public_assets: var("PUBLIC_ASSETS").is_none() || flag_var(var, "PUBLIC_ASSETS"),
+            robots_txt: var("ROBOTS_TXT")
+                .map(|v| v.replace("\\n", "\n"))
+                .unwrap_or(String::from("User-agent: *\nDisallow: /\n")),
+            index_document: key_var(var, "INDEX_DOCUMENT"),
+            allowed_extensions: extension_var(var, "ALLOWED_EXTENSIONS"),
+            blocked_extensions: extension_var(var, "BLOCKED_EXTENSIONS"),
+            retry_attempts: number_var(var, "RETRY_ATTEMPTS", 2),
+            serve_precompressed: flag_var(var, "SERVE_PRECOMPRESSED"),
+            put_slash_as_mkcol: flag_var(var, "PUT_SLASH_AS_MKCOL"),
+            s3_import: flag_var(var, "ENABLE_S3_IMPORT"),
+            canonical_host: var("CANONICAL_HOST")
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty()),
+            write_only_prefixes: key_list_var(var, "WRITE_ONLY_PREFIXES"),
+            signing_secret: var("SIGNING_SECRET").filter(|v| !v.is_empty()),
+            admin: var("ADMIN_USERNAME")
+                .zip(var("ADMIN_PASSWORD"))
+                .filter(|(user, pass)| !user.is_empty() && !pass.is_empty()),
+            listing_template: var("LISTING_TEMPLATE").filter(|v| !v.trim().is_empty()),
+            listing_template_key: key_var(var, "LISTING_TEMPLATE_KEY"),
+            root_usage: flag_var(var, "ROOT_USAGE"),
+            mount_path: var("MOUNT_PATH")
+                .map(|v| v.trim().trim_matches('/').to_string())
+                .filter(|v| !v.is_empty())
+                .map(|v| format!("/{}", v))
+                .unwrap_or_default(),
+            custom_namespace: var("CUSTOM_NAMESPACE")
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| String::from(CUSTOM_NAMESPACE)),
+        }
+    }
+
+    /// Whether `credentials`, as `basic_credentials` decodes them, are the
+    /// admin's. Always false while no admin is configured.
+    pub fn is_admin(&self, credentials: Option<&(String, String)>) -> bool {
+        self.admin.is_some() && credentials == self.admin.as_ref()
+    }
+
+    /// Whether `key` lies in an upload-only folder of `WRITE_ONLY_PREFIXES`,
+    /// the folder itself included: it can be written to but never read or
+    /// listed.
+    pub fn is_write_only(&self, key: &str) -> bool {
+        self.write_only_prefixes.iter().any(|p| {
+            key.strip_prefix(p.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    /// Whether an upload passes `ALLOWED_EXTENSIONS` and `BLOCKED_EXTENSIONS`.
+    /// Entries containing a `/` are media types matched against the declared
+    /// `Content-Type`, so renaming `x.exe` to `x.txt` doesn't get it past a
+    /// blocked `application/x-msdownload`. Matching ignores case.
+    pub fn accepts_upload(&self, key: &str, content_type: Option<&str>) -> bool {
+        let name = key.rsplit('/').next().unwrap_or(key);
+        let extension = name.rsplit_once('.').map(|(_, e)| e.to_lowercase());
+        let media_type = content_type
+            .and_then(|t| t.split(';').next())
+            .map(|t| t.trim().to_lowercase());
+        let matches = |entry: &String| {
+            Some(entry) == extension.as_ref() || Some(entry) == media_type.as_ref()
+        };
+        if self.blocked_extensions.iter().any(matches) {
+            return false;
+        }
+        self.allowed_extensions.is_empty() || self.allowed_extensions.iter().any(matches)
+    }
+
+    /// A key is hidden when a hidden prefix matches whole segments of it,
+    /// starting at any of them, so `.DS_Store` is hidden in every folder, not
+    /// only at the root, while `.davrc` stays visible next to `.dav`.
+    pub fn is_hidden(&self, key: &str) -> bool {
+        std::iter::once(0)
+            .chain(key.match_indices('/').map(|(i, _)| i + 1))
+            .any(|i| {
+                self.hidden_prefixes.iter().any(|p| {
+                    key[i..]
+                        .strip_prefix(p.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+                })
+            })
+    }
+
+    /// Whether `key` shows up in a listing of `collection`. The trash and the
+    /// versions stay out of every other listing, but list what they keep when
+    /// they are listed themselves.
+    pub fn is_listed_in(&self, collection: &str, key: &str) -> bool {
+        if kept_for(collection).is_some() {
+            let apple_double = self.filter_apple_double && is_apple_double(key);
+            return self.is_kept(key) && !apple_double;
+        }
+        self.is_listed(key)
+    }
+
+    /// Whether `key` is something the trash or the versions keep and that can
+    /// still be restored, unlike everything else that is hidden: the file it
+    /// was kept for must not be hidden or upload-only itself.
+    pub fn is_kept(&self, key: &str) -> bool {
+        kept_for(key).is_some_and(|original| {
+            original.is_empty() || (!self.is_hidden(original) && !self.is_write_only(original))
+        })
+    }
+
+    /// Whether a key shows up in PROPFIND and SEARCH results. An upload-only
+    /// folder is listed in its parent, but its contents never are. `key` is
+    /// the full key, not a name relative to the listed collection, since
+    /// `WRITE_ONLY_PREFIXES` are matched from the root.
+    pub fn is_listed(&self, key: &str) -> bool {
+        let key_trimmed = key.trim_end_matches('/');
+        let write_only_member = self.is_write_only(key_trimmed)
+            && !self.write_only_prefixes.iter().any(|p| p == key_trimmed);
+        let apple_double = self.filter_apple_double && is_apple_double(key);
+        !(self.is_hidden(key) || apple_double || write_only_member)
+    }
+
+    /// The origin to answer CORS with, or `None` when the request origin is
+    /// not allowed. Without `ALLOWED_ORIGINS` any origin is reflected, except
+    /// with `CORS_ALLOW_CREDENTIALS`: any site could then act with the
+    /// visitor's credentials, so only origins listed by name are answered
+    /// and, without a list, no CORS headers are sent at all.
+    pub fn cors_origin(&self, origin: Option<String>) -> Option<String> {
+        match &self.allowed_origins {
+            Some(allowed) if !allowed.iter().any(|o| o == "*") => {
+                origin.filter(|o| allowed.contains(o))
+            }
+            _ if self.cors_allow_credentials => None,
+            _ => origin.or(Some(String::from("*"))),
+        }
+    }
+
+    /// The compliance classes advertised in the `DAV` header, each listed
+    /// only while the feature behind it is on.
+    pub fn dav_classes(&self) -> Vec<&'static str> {
+        [
+            ("1", true),
+            // LOCK and UNLOCK, which are always on
+            ("2", true),
+            // claimed only together with BIND, the one extension clients
+            // probing for class 3 go on to use
+            ("3", self.bind),
+            ("bind", self.bind),
+            ("version-control", self.versioning),
+        ]
+        .into_iter()
+        .filter_map(|(class, enabled)| enabled.then_some(class))
+        .collect()
+    }
+
+    /// What `GET /.dav/info` reports, so operators can check which settings
+    /// took effect. Credentials and URLs are deliberately left out.
+    pub fn describe(&self) -> Value {
+        json!({
+            "bucket": BUCKET_BINDING,
+            "mount_path": self.mount_path,
+            // Only share links read without Basic credentials, and only the
+            // files they were minted for.
+            "anonymous_read": self.signing_secret.is_some(),
+            "hidden_prefixes": self.hidden_prefixes,
+            "allowed_origins": self.allowed_origins,
+            "index_document": self.index_document,
+            "listing_template_key": self.listing_template_key,
+            "canonical_host": self.canonical_host,
+            "write_only_prefixes": self.write_only_prefixes,
+            "dav": self.dav_classes(),
+            "custom_namespace": self.custom_namespace,
+            "features": {
+                "locking": true,
+                "versioning": self.versioning,
+                "bind": self.bind,
+                "compression": self.serve_precompressed,
+                "soft_delete": self.soft_delete,
+                "edge_cache": self.edge_cache,
+                "sniff_content_type": self.sniff_content_type,
+                "filter_apple_double": self.filter_apple_double,
+                "origin_fallback": self.origin_fallback_url.is_some(),
+                "cors_allow_credentials": self.cors_allow_credentials,
+                "public_assets": self.public_assets,
+                "s3_import": self.s3_import,
+                "share_links": self.signing_secret.is_some(),
+                "admin": self.admin.is_some(),
+                "root_usage": self.root_usage,
+                "listing_template": self.listing_template.is_some()
+                    || self.listing_template_key.is_some(),
+            },
+            "limits": {
+                "max_propfind_entries": self.max_propfind_entries,
+                "max_listed_objects": self.max_listed_objects,
+                "propfind_time_budget_ms": self.propfind_time_budget_ms,
+                "edge_cache_ttl": self.edge_cache_ttl,
+                "request_timeout_ms": self.request_timeout_ms,
+                "trash_retention_days": self.trash_retention_days,
+                "cors_max_age": self.cors_max_age,
+                "list_page_size": self.list_page_size,
+                "retry_attempts": self.retry_attempts,
+            },
+        })
+    }
+}
+
+/// How far `key` reaches into what the trash or the versions keep:
+/// `docs/a.txt` for `.trash/{ms}/docs/a.txt`, `docs/a.txt/{ms}` for a version
+/// of it, and nothing yet for `.trash`, `.trash/{ms}` or `.versions`. `None`
+/// outside of both.
+pub fn kept_for(key: &str) -> Option<&str> {
+    let below = |prefix: &str| {
+        key.strip_prefix(prefix).and_then(|rest| match rest {
+            "" | "/" => Some(""),
+            rest => rest.strip_prefix('/'),
+        })
+    };
+    if let Some(rest) = below(TRASH_PREFIX) {
+        return Some(rest.split_once('/').map_or("", |(_, key)| key));
+    }
+    below(VERSIONS_PREFIX)
+}
+
+/// macOS litters shares with `._*` resource forks and `.DS_Store` files.
+pub fn is_apple_double(key: &str) -> bool {
+    let name = key.trim_end_matches('/').rsplit('/').next().unwrap_or(key);
+    name.starts_with("._") || name == ".DS_Store"
+}
+
+/// Looks an env var up by name.
+type Vars<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// Comma-separated env var, with surrounding slashes and whitespace trimmed.
+fn list_var(var: Vars, name: &str) -> Vec<String> {
+    var(name)
+        .map(|v| {
+            v.split(',')
+                .map(|s| s.trim().trim_matches('/').to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Like `list_var`, each entry spelled the way `path::normalize_key` spells
+/// the keys it is matched against, so `r%c3%a9sum%c3%a9`, `résumé` and
+/// `a//b/` work as well as `r%C3%A9sum%C3%A9` and `a/b`.
+fn key_list_var(var: Vars, name: &str) -> Vec<String> {
+    list_var(var, name)
+        .iter()
+        .map(|entry| normalize_key(entry))
+        .filter(|key| !key.is_empty())
+        .collect()
+}
+
+/// A single key, normalized like the entries of `key_list_var`.
+fn key_var(var: Vars, name: &str) -> Option<String> {
+    var(name)
+        .map(|v| normalize_key(v.trim()))
+        .filter(|key| !key.is_empty())
+}
+
+/// Like `list_var`, lowercased and with a leading `.` dropped from extensions.
+fn extension_var(var: Vars, name: &str) -> Vec<String> {
+    list_var(var, name)
+        .iter()
+        .map(|e| e.trim_start_matches('.').to_lowercase())
+        .collect()
+}
+
+/// Boolean env var; anything other than `true`/`1` counts as off.
+fn flag_var(var: Vars, name: &str) -> bool {
+    var(name).is_some_and(|v| matches!(v.trim(), "true" | "1"))
+}
+
+/// Numeric env var, falling back to `default` when unset or unparsable.
+fn number_var<T: FromStr>(var: Vars, name: &str, default: T) -> T {
+    var(name)
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn config(vars: &[(&str, &str)]) -> Config {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        Config::from_vars(|name| vars.get(name).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn hidden_names_match_whole_segments_in_any_folder() {
+        let config = config(&[("HIDDEN_PREFIXES", ".DS_Store, .well-known/acme")]);
+        assert!(config.is_hidden(".DS_Store"));
+        assert!(config.is_hidden("photos/2024/.DS_Store"));
+        assert!(config.is_hidden(".well-known/acme"));
+        assert!(config.is_hidden("site/.well-known/acme/token"));
+        assert!(!config.is_hidden(".well-known"));
+        assert!(!config.is_hidden(".well-known/acme-challenge"));
+    }
+
+    #[test]
+    fn near_misses_of_a_hidden_name_stay_visible() {
+        let config = config(&[]);
+        assert!(config.is_hidden(".dav"));
+        assert!(config.is_hidden(".dav/listing.html"));
+        assert!(config.is_hidden("docs/.dav"));
+        assert!(!config.is_hidden(".davrc"));
+        assert!(!config.is_hidden("docs/.davrc"));
+        assert!(!config.is_hidden("my.dav"));
+        assert!(!config.is_hidden("dav/.daV"));
+    }
+
+    #[test]
+    fn hidden_names_are_not_string_prefixes() {
+        let config = config(&[("HIDDEN_PREFIXES", "._")]);
+        assert!(config.is_hidden("._"));
+        assert!(!config.is_hidden("._report.pdf"));
+        assert!(!config.is_hidden("docs/._report.pdf"));
+    }
+
+    #[test]
+    fn key_vars_are_spelled_like_keys() {
+        let config = config(&[
+            ("HIDDEN_PREFIXES", "r%c3%a9sum%c3%a9, drafts//old/"),
+            ("WRITE_ONLY_PREFIXES", "/inbox/./uploads/"),
+            ("INDEX_DOCUMENT", "/index.html"),
+            ("LISTING_TEMPLATE_KEY", "templates/listing%2ehtml"),
+        ]);
+        assert!(config.is_hidden("docs/r%C3%A9sum%C3%A9"));
+        assert!(config.is_hidden("drafts/old/a.txt"));
+        assert!(config.is_write_only("inbox/uploads/a.txt"));
+        assert_eq!(config.index_document.as_deref(), Some("index.html"));
+        assert_eq!(
+            config.listing_template_key.as_deref(),
+            Some("templates/listing.html")
+        );
+    }
+
+    #[test]
+    fn origins_are_reflected_unless_a_list_leaves_them_out() {
+        let open = config(&[]);
+        assert_eq!(
+            open.cors_origin(Some("https://a.example".into()))
+                .as_deref(),
+            Some("https://a.example")
+        );
+        assert_eq!(open.cors_origin(None).as_deref(), Some("*"));
+        let listed = config(&[("ALLOWED_ORIGINS", "https://a.example, https://b.example")]);
+        assert_eq!(
+            listed
+                .cors_origin(Some("https://b.example".into()))
+                .as_deref(),
+            Some("https://b.example")
+        );
+        assert_eq!(listed.cors_origin(Some("https://c.example".into())), None);
+        assert_eq!(listed.cors_origin(None), None);
+        let any = config(&[("ALLOWED_ORIGINS", "*")]);
+        assert_eq!(
+            any.cors_origin(Some("https://c.example".into())).as_deref(),
+            Some("https://c.example")
+        );
+    }
+
+    #[test]
+    fn credentials_are_only_shared_with_listed_origins() {
+        let origin = || Some("https://a.example".to_string());
+        let unlisted = config(&[("CORS_ALLOW_CREDENTIALS", "true")]);
+        assert_eq!(unlisted.cors_origin(origin()), None);
+        assert_eq!(unlisted.cors_origin(None), None);
+        let wildcard = config(&[("CORS_ALLOW_CREDENTIALS", "true"), ("ALLOWED_ORIGINS", "*")]);
+        assert_eq!(wildcard.cors_origin(origin()), None);
+        let listed = config(&[
+            ("CORS_ALLOW_CREDENTIALS", "true"),
+            ("ALLOWED_ORIGINS", "https://a.example"),
+        ]);
+        assert_eq!(
+            listed.cors_origin(origin()).as_deref(),
+            Some("https://a.example")
+        );
+        assert_eq!(listed.cors_origin(Some("https://b.example".into())), None);
+    }
+
+    #[test]
+    fn cors_max_age_defaults_to_a_day() {
+        assert_eq!(config(&[]).cors_max_age, 86400);
+        assert_eq!(config(&[("CORS_MAX_AGE", " 600 ")]).cors_max_age, 600);
+        assert_eq!(config(&[("CORS_MAX_AGE", "soon")]).cors_max_age, 86400);
+    }
+
+    #[test]
+    fn apple_double_files_are_listed_unless_filtered() {
+        for key in ["._report.pdf", "docs/._report.pdf", "docs/.DS_Store"] {
+            assert!(config(&[]).is_listed(key), "{}", key);
+            assert!(
+                !config(&[("FILTER_APPLE_DOUBLE", "true")]).is_listed(key),
+                "{}",
+                key
+            );
+        }
+        let filtered = config(&[("FILTER_APPLE_DOUBLE", "1")]);
+        assert!(filtered.is_listed("docs/_report.pdf"));
+        assert!(filtered.is_listed("docs/report._pdf"));
+        assert!(filtered.is_listed("docs/.DS_Store.txt"));
+    }
+
+    #[test]
+    fn uploads_pass_without_filters() {
+        assert!(config(&[]).accepts_upload("a/setup.exe", Some("application/x-msdownload")));
+        assert!(config(&[]).accepts_upload("README", None));
+    }
+
+    #[test]
+    fn extension_filters_ignore_case_and_dots() {
+        let blocked = config(&[("BLOCKED_EXTENSIONS", ".EXE, bat")]);
+        assert!(!blocked.accepts_upload("a/Setup.exe", None));
+        assert!(!blocked.accepts_upload("run.BAT", None));
+        assert!(blocked.accepts_upload("exe", None));
+        assert!(blocked.accepts_upload("notes.exe.txt", None));
+        let allowed = config(&[("ALLOWED_EXTENSIONS", "jpg,png")]);
+        assert!(allowed.accepts_upload("photos/a.JPG", None));
+        assert!(!allowed.accepts_upload("photos/a.gif", None));
+        assert!(!allowed.accepts_upload("photos/jpg", None));
+    }
+
+    #[test]
+    fn media_type_filters_match_the_declared_type() {
+        let blocked = config(&[("BLOCKED_EXTENSIONS", "application/x-msdownload")]);
+        assert!(!blocked.accepts_upload("x.txt", Some("Application/X-MSDownload; v=1")));
+        assert!(blocked.accepts_upload("x.exe", Some("text/plain")));
+        assert!(blocked.accepts_upload("x.exe", None));
+        let allowed = config(&[("ALLOWED_EXTENSIONS", "image/png, pdf")]);
+        assert!(allowed.accepts_upload("scan", Some("image/png")));
+        assert!(allowed.accepts_upload("scan.pdf", Some("application/octet-stream")));
+        assert!(!allowed.accepts_upload("scan", Some("image/gif")));
+    }
+
+    #[test]
+    fn blocked_entries_win_over_allowed_ones() {
+        let config = config(&[
+            ("ALLOWED_EXTENSIONS", "png"),
+            ("BLOCKED_EXTENSIONS", "image/png"),
+        ]);
+        assert!(!config.accepts_upload("a.png", Some("image/png")));
+    }
+
+    #[test]
+    fn dav_header_follows_the_features() {
+        assert_eq!(config(&[]).dav_classes(), ["1", "2", "3", "bind"]);
+        assert_eq!(
+            config(&[("VERSIONING", "true")]).dav_classes(),
+            ["1", "2", "3", "bind", "version-control"]
+        );
+        assert_eq!(
+            config(&[("ENABLE_BIND", "false")]).dav_classes(),
+            ["1", "2"]
+        );
+        assert_eq!(
+            config(&[("ENABLE_BIND", "0"), ("VERSIONING", "1")]).dav_classes(),
+            ["1", "2", "version-control"]
+        );
+    }
+
+    #[test]
+    fn only_the_configured_admin_is_admin() {
+        let admin = Some(("root".to_string(), "s3cret".to_string()));
+        assert!(!config(&[]).is_admin(None));
+        assert!(!config(&[]).is_admin(admin.as_ref()));
+        assert!(!config(&[("ADMIN_USERNAME", "root")]).is_admin(admin.as_ref()));
+        let config = config(&[("ADMIN_USERNAME", "root"), ("ADMIN_PASSWORD", "s3cret")]);
+        assert!(config.is_admin(admin.as_ref()));
+        assert!(!config.is_admin(None));
+        assert!(!config.is_admin(Some(&("root".to_string(), "S3cret".to_string()))));
+        assert!(!config.is_admin(Some(&("user".to_string(), "s3cret".to_string()))));
+    }
+}
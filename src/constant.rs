@@ -1,4 +1,4 @@
-pub const METHODS: [&str; 12] = [
+pub const METHODS: [&str; 16] = [
     "GET",
     "DELETE",
     "PROPPATCH",
@@ -11,18 +11,34 @@ pub const METHODS: [&str; 12] = [
     "LOCK",
     "UNLOCK",
     "PUT",
+    "SEARCH",
+    "POST",
+    "BIND",
+    "UNBIND",
 ];
 
-pub const ALLOW_HEADERS: [&str; 6] = [
+pub const ALLOW_HEADERS: [&str; 16] = [
     "Authorization",
     "Content-Type",
+    "Content-Disposition",
     "Depth",
     "Overwrite",
     "Destination",
+    "Prefer",
     "Range",
+    "If",
+    "If-Match",
+    "If-None-Match",
+    "If-Range",
+    "Lock-Token",
+    "Timeout",
+    "X-Requested-With",
+    "X-Object-Tags",
 ];
 
-pub const EXPOSED_HEADERS: [&str; 10] = [
+pub const EXPOSED_HEADERS: [&str; 13] = [
+    "Accept-Ranges",
+    "Content-Disposition",
     "Content-Length",
     "Content-Type",
     "Content-Range",
@@ -33,4 +49,48 @@ pub const EXPOSED_HEADERS: [&str; 10] = [
     "Location",
     "Lock-Token",
     "X-WebDAV-Status",
+    "X-Next-Cursor",
+];
+
+/// Largest object the edge cache will buffer and store.
+pub const EDGE_CACHE_MAX_SIZE: u64 = 25 * 1024 * 1024;
+
+/// Seconds the root's `object-count` and `total-bytes` are cached, since
+/// counting lists the whole bucket.
+pub const ROOT_USAGE_TTL: u64 = 60;
+
+/// Largest fallback response buffered to be copied into the bucket; bigger
+/// ones are streamed through without being stored.
+pub const FALLBACK_STORE_MAX_SIZE: u64 = 25 * 1024 * 1024;
+
+/// Media types `?content-type=` may force on GET. None of them is rendered
+/// as active content, so user uploads can't be turned into HTML or SVG that
+/// runs script on this origin.
+pub const CONTENT_TYPE_OVERRIDES: [&str; 9] = [
+    "text/plain",
+    "text/csv",
+    "application/json",
+    "application/octet-stream",
+    "application/pdf",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
 ];
+
+/// Namespace of the properties this server adds on top of `DAV:`, unless
+/// `CUSTOM_NAMESPACE` names another.
+pub const CUSTOM_NAMESPACE: &str = "urn:cloudflare-webdav";
+/// Prefix bound to that namespace on every multistatus root.
+pub const CUSTOM_PREFIX: &str = "cw";
+/// S3-style user metadata, kept in custom metadata under the header name.
+pub const AMZ_META_PREFIX: &str = "x-amz-meta-";
+/// Custom metadata entry holding the dead properties PROPPATCH set, as a
+/// JSON object from `{namespace}name` to the text value.
+pub const DEAD_PROPS_KEY: &str = "props";
+/// R2 refuses custom metadata of more than 2 KiB per object, keys included.
+pub const MAX_METADATA_LEN: usize = 2048;
+
+/// Multistatus and lock bodies may carry UTF-8 file names, so the charset is
+/// spelled out rather than left to the XML declaration.
+pub const XML_CONTENT_TYPE: &str = "application/xml; charset=utf-8";
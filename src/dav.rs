@@ -1,6 +1,12 @@
-use chrono::Utc;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
 use worker::Object;
 
+use crate::constant::{AMZ_META_PREFIX, CUSTOM_PREFIX, DEAD_PROPS_KEY};
+use crate::lock::Lock;
+use crate::xml::escape;
+
 #[derive(Debug, Clone)]
 pub struct DavBuilder {
     creation_date: String,
@@ -10,34 +16,66 @@ pub struct DavBuilder {
     get_last_modified: String,
     resource_type: String,
     href: String,
+    is_collection: bool,
+    tags: Option<String>,
+    content_disposition: Option<String>,
+    amz_meta: Vec<(String, String)>,
+    /// Namespace, name and value of each property PROPPATCH stored.
+    dead_props: Vec<(String, String, String)>,
+    active_lock: Option<String>,
+    usage: Option<(usize, u64)>,
 }
 impl DavBuilder {
     pub fn new() -> Self {
         Self {
-            creation_date: Utc::now().to_string(),
+            creation_date: creation_date(Utc::now()),
             get_content_length: None,
             get_content_type: "httpd/unix-directory".to_string(),
             get_etag: None,
-            get_last_modified: Utc::now().to_string(),
+            get_last_modified: last_modified(Utc::now()),
             resource_type: "<collection />".to_string(),
             href: String::new(),
+            is_collection: true,
+            tags: None,
+            content_disposition: None,
+            amz_meta: vec![],
+            dead_props: vec![],
+            active_lock: None,
+            usage: None,
         }
     }
 
     pub fn object(mut self, href: impl AsRef<str>, object: Option<&Object>) -> Self {
-        let uploaded_time = object
-            .map(|o| o.uploaded().to_string())
-            .unwrap_or(Utc::now().to_string());
+        let uploaded = object.map(uploaded).unwrap_or_else(Utc::now);
 
-        self.creation_date = uploaded_time.clone();
+        self.creation_date = creation_date(uploaded);
         self.href = href.as_ref().to_string();
+        self.is_collection = object.is_none();
+        self.tags = object
+            .and_then(|o| o.custom_metadata().ok())
+            .and_then(|c| c.get("tags").cloned());
+        self.content_disposition = object.and_then(|o| o.http_metadata().content_disposition);
+        let mut amz_meta = object
+            .and_then(|o| o.custom_metadata().ok())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(AMZ_META_PREFIX))
+            .collect::<Vec<_>>();
+        amz_meta.sort();
+        self.amz_meta = amz_meta;
+        self.dead_props = object
+            .and_then(|o| o.custom_metadata().ok())
+            .and_then(|c| c.get(DEAD_PROPS_KEY).cloned())
+            .map(|props| dead_props(&props))
+            .unwrap_or_default();
 
         self.get_content_length = object.map(|o| o.size().to_string());
         self.get_content_type = object
             .and_then(|o| o.http_metadata().content_type)
             .unwrap_or("httpd/unix-directory".to_string());
-        self.get_etag = object.map(|o| o.etag());
-        self.get_last_modified = uploaded_time;
+        // `getetag` must hold the quoted entity tag, exactly as sent in `ETag`.
+        self.get_etag = object.map(|o| o.http_etag());
+        self.get_last_modified = last_modified(uploaded);
         self.resource_type = object
             .map(|o| {
                 o.custom_metadata()
@@ -50,9 +88,90 @@ impl DavBuilder {
         self
     }
 
+    pub fn lock(mut self, lock: Option<&Lock>) -> Self {
+        self.active_lock = lock.map(Lock::activelock);
+        self
+    }
+
+    /// How many files the share holds and their total size, reported on the
+    /// root.
+    pub fn usage(mut self, usage: Option<(usize, u64)>) -> Self {
+        self.usage = usage;
+        self
+    }
+
     pub fn build(self) -> String {
-        let content_length_str = self.get_content_length.unwrap_or_default();
         let etag = self.get_etag.unwrap_or_default();
+        // Collections carry neither a length nor a content type, and accept
+        // new members by POST to their own URL. Leaving `getcontentlength`
+        // out, as RFC 4918 allows, suits more clients than reporting `0`,
+        // which some show as an empty file. No REPORT is implemented, so
+        // the report set is advertised empty.
+        let file_props = if self.is_collection {
+            format!(
+                r#"
+            <add-member><href>{}</href></add-member>
+            <supported-report-set/>"#,
+                escape(&self.href)
+            )
+        } else {
+            format!(
+                r#"
+            <getcontentlength>{}</getcontentlength>
+            <getcontenttype>{}</getcontenttype>"#,
+                self.get_content_length.unwrap_or_default(),
+                self.get_content_type,
+            )
+        };
+
+        let mut custom_props = match self.tags {
+            Some(tags) => format!(
+                r#"
+            <{0}:tags>{1}</{0}:tags>"#,
+                CUSTOM_PREFIX,
+                escape(&tags)
+            ),
+            None => String::new(),
+        };
+        if let Some(disposition) = self.content_disposition {
+            custom_props.push_str(&format!(
+                r#"
+            <{0}:content-disposition>{1}</{0}:content-disposition>"#,
+                CUSTOM_PREFIX,
+                escape(&disposition)
+            ));
+        }
+        for (name, value) in &self.amz_meta {
+            custom_props.push_str(&format!(
+                r#"
+            <{0}:{1}>{2}</{0}:{1}>"#,
+                CUSTOM_PREFIX,
+                name,
+                escape(value)
+            ));
+        }
+        for (namespace, name, value) in &self.dead_props {
+            custom_props.push_str(&format!(
+                r#"
+            <{0} xmlns="{1}">{2}</{0}>"#,
+                name,
+                escape(namespace),
+                escape(value)
+            ));
+        }
+        if let Some((count, bytes)) = self.usage {
+            custom_props.push_str(&format!(
+                r#"
+            <{0}:object-count>{1}</{0}:object-count>
+            <{0}:total-bytes>{2}</{0}:total-bytes>"#,
+                CUSTOM_PREFIX, count, bytes
+            ));
+        }
+
+        let lock_discovery = match self.active_lock {
+            Some(lock) => format!("<lockdiscovery>{}</lockdiscovery>", lock),
+            None => String::from("<lockdiscovery/>"),
+        };
 
         format!(
             r#"<response>
@@ -60,10 +179,9 @@ impl DavBuilder {
         <propstat>
             <prop>
             <resourcetype>{}</resourcetype>
-            <creationdate>{}</creationdate>
-            <getcontentlength>{}</getcontentlength>
+            <creationdate>{}</creationdate>{}
             <getlastmodified>{}</getlastmodified>
-            <getetag>{}</getetag>
+            <getetag>{}</getetag>{}
             <supportedlock>
                     <lockentry>
                         <lockscope>
@@ -82,19 +200,102 @@ impl DavBuilder {
                         </locktype>
                     </lockentry>
                 </supportedlock>
-                <lockdiscovery/>
-            <getcontenttype>{}</getcontenttype>
+                {}
             </prop>
             <status>HTTP/1.1 200 OK</status>
         </propstat>
     </response>"#,
-            self.href,
+            escape(&self.href),
             self.resource_type,
-            self.get_content_type,
-            content_length_str,
+            self.creation_date,
+            file_props,
             self.get_last_modified,
             etag,
-            self.get_content_type,
+            custom_props,
+            lock_discovery,
         )
     }
 }
+
+/// The stored dead properties, `{namespace}name` keys split up and sorted.
+fn dead_props(json: &str) -> Vec<(String, String, String)> {
+    let props: BTreeMap<String, String> = serde_json::from_str(json).unwrap_or_default();
+    props
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let (namespace, name) = key.strip_prefix('{')?.split_once('}')?;
+            Some((namespace.to_string(), name.to_string(), value))
+        })
+        .collect()
+}
+
+/// `creationdate` is an RFC 3339 timestamp.
+fn creation_date(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// When `object` was uploaded, which R2 also reports as its modification.
+pub fn uploaded(object: &Object) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(object.uploaded().as_millis() as i64)
+        .single()
+        .unwrap_or_else(Utc::now)
+}
+
+/// The `Last-Modified` of a GET and the `getlastmodified` of a PROPFIND, kept
+/// byte-identical so sync clients comparing them don't download again.
+pub fn last_modified_of(object: &Object) -> String {
+    last_modified(uploaded(object))
+}
+
+/// `getlastmodified` is an HTTP-date, as in `Last-Modified`.
+fn last_modified(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dead_props_split_their_clark_names() {
+        let json = r#"{"{urn:schemas-microsoft-com:}Win32FileAttributes":"00000020","{}note":"hi","bad":"x"}"#;
+        assert_eq!(
+            dead_props(json),
+            vec![
+                (
+                    String::from("urn:schemas-microsoft-com:"),
+                    String::from("Win32FileAttributes"),
+                    String::from("00000020")
+                ),
+                (String::new(), String::from("note"), String::from("hi")),
+            ]
+        );
+        assert!(dead_props("not json").is_empty());
+    }
+
+    #[test]
+    fn creationdate_is_rfc3339() {
+        let time = Utc.with_ymd_and_hms(2024, 3, 5, 7, 8, 9).unwrap();
+        assert_eq!(creation_date(time), "2024-03-05T07:08:09Z");
+    }
+
+    #[test]
+    fn getlastmodified_is_an_http_date() {
+        let time = Utc.with_ymd_and_hms(2024, 3, 5, 7, 8, 9).unwrap();
+        assert_eq!(last_modified(time), "Tue, 05 Mar 2024 07:08:09 GMT");
+    }
+
+    #[test]
+    fn collections_have_no_content_type() {
+        let response = DavBuilder::new().object("/docs/", None).build();
+        assert!(response.contains("<resourcetype><collection /></resourcetype>"));
+        assert!(!response.contains("<getcontenttype>"));
+    }
+
+    #[test]
+    fn collections_have_no_length_but_accept_members() {
+        let response = DavBuilder::new().object("/a b/", None).build();
+        assert!(!response.contains("<getcontentlength>"));
+        assert!(response.contains("<add-member><href>/a b/</href></add-member>"));
+    }
+}
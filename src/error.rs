@@ -0,0 +1,71 @@
+use worker::{console_error, Error, Response, Result};
+
+/// Turns a handler error into a response instead of letting the runtime answer
+/// with a bare 500. The details are logged, never sent.
+pub fn error_response(err: &Error) -> Result<Response> {
+    console_error!("[ERROR] {}", err);
+    let (text, status) = status_of(&err.to_string());
+    Response::error(text, status)
+}
+
+/// The status an error message maps to. The binding reports R2 failures only
+/// as messages, so the kind is recognised by what they say.
+fn status_of(message: &str) -> (&'static str, u16) {
+    let message = message.to_lowercase();
+    if message.contains("is none") || message.contains("not found") {
+        ("Not Found", 404)
+    } else if message.contains("quota") || message.contains("storage limit") {
+        // The bucket or account is full; WebDAV clients know to say so.
+        ("Insufficient Storage", 507)
+    } else if message.contains("precondition") {
+        ("Precondition Failed", 412)
+    } else if message.contains("too large") || message.contains("exceeds") {
+        ("Payload Too Large", 413)
+    } else if message.contains("too many requests") || message.contains("rate limit") {
+        ("Service Unavailable", 503)
+    } else {
+        ("Internal Server Error", 500)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_objects_are_not_found() {
+        assert_eq!(status_of("Body is None"), ("Not Found", 404));
+        assert_eq!(status_of("Object Not Found"), ("Not Found", 404));
+    }
+
+    #[test]
+    fn other_failures_map_by_message() {
+        assert_eq!(
+            status_of("PreconditionFailed (10031)"),
+            ("Precondition Failed", 412)
+        );
+        assert_eq!(status_of("Entity too large"), ("Payload Too Large", 413));
+        assert_eq!(
+            status_of("Upload exceeds the maximum size"),
+            ("Payload Too Large", 413)
+        );
+        assert_eq!(status_of("Too Many Requests"), ("Service Unavailable", 503));
+        assert_eq!(
+            status_of("account rate limit hit"),
+            ("Service Unavailable", 503)
+        );
+        assert_eq!(
+            status_of("network connection lost"),
+            ("Internal Server Error", 500)
+        );
+    }
+
+    #[test]
+    fn full_storage_is_insufficient_storage() {
+        assert_eq!(status_of("Quota exceeded"), ("Insufficient Storage", 507));
+        assert_eq!(
+            status_of("Bucket storage limit reached"),
+            ("Insufficient Storage", 507)
+        );
+    }
+}
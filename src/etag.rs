@@ -0,0 +1,75 @@
+//! [Entity tag comparison](https://www.rfc-editor.org/rfc/rfc9110#name-comparison-2)
+//! for `If-Match` and `If-None-Match` lists.
+
+/// The tags of a list such as `"a", W/"b"`, each with any `W/` prefix kept.
+/// Tags may contain commas, so they are split at their quotes.
+fn tags(header: &str) -> Vec<&str> {
+    let mut tags = vec![];
+    let mut rest = header;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        let start = rest.len();
+        let quoted = rest.strip_prefix("W/").unwrap_or(rest);
+        let end = match quoted.strip_prefix('"').and_then(|q| q.find('"')) {
+            Some(end) => start - quoted.len() + end + 2,
+            None => break,
+        };
+        tags.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    tags
+}
+
+fn is_weak(tag: &str) -> bool {
+    tag.starts_with("W/")
+}
+
+fn opaque(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+/// Whether an `If-Match` list holds for the current `etag`: `*`, or a tag
+/// equal to it under strong comparison, where weak tags never match.
+pub fn strong_match(header: &str, etag: &str) -> bool {
+    header.trim() == "*"
+        || (!is_weak(etag) && tags(header).iter().any(|tag| !is_weak(tag) && *tag == etag))
+}
+
+/// Whether an `If-None-Match` list names the current `etag`, failing the
+/// condition: `*`, or a tag equal to it once `W/` is ignored on both sides.
+pub fn weak_match(header: &str, etag: &str) -> bool {
+    header.trim() == "*" || tags(header).iter().any(|tag| opaque(tag) == opaque(etag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_split_at_quotes() {
+        assert_eq!(
+            tags(r#""a", W/"b","c,d""#),
+            vec![r#""a""#, r#"W/"b""#, r#""c,d""#]
+        );
+        assert!(tags("").is_empty());
+    }
+
+    #[test]
+    fn strong_match_ignores_weak_tags() {
+        assert!(strong_match("*", r#""a""#));
+        assert!(strong_match(r#""x", "a""#, r#""a""#));
+        assert!(strong_match(r#""a,b""#, r#""a,b""#));
+        assert!(!strong_match(r#"W/"a""#, r#""a""#));
+        assert!(!strong_match(r#""a""#, r#"W/"a""#));
+        assert!(!strong_match(r#""b""#, r#""a""#));
+    }
+
+    #[test]
+    fn weak_match_ignores_the_weak_prefix() {
+        assert!(weak_match(" * ", r#""a""#));
+        assert!(weak_match(r#"W/"a""#, r#""a""#));
+        assert!(weak_match(r#""a""#, r#"W/"a""#));
+        assert!(weak_match(r#""x", "abc-2""#, r#""abc-2""#));
+        assert!(!weak_match(r#""b""#, r#""a""#));
+    }
+}
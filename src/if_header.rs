@@ -0,0 +1,139 @@
+//! [If header](http://www.webdav.org/specs/rfc4918.html#HEADER_If) parsing.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    /// A state token such as `opaquelocktoken:...`, or `DAV:no-lock`.
+    Token(String),
+    /// A quoted entity tag, as in `["abc"]`.
+    ETag(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub not: bool,
+    pub state: State,
+}
+
+/// A parenthesised list: all of its conditions must hold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct List {
+    /// The resource tag the list applies to; `None` means the request URL.
+    pub resource: Option<String>,
+    pub conditions: Vec<Condition>,
+}
+
+/// Splits a header into its lists, each carrying the resource tag written
+/// before it: `<http://a/x> (<token>) <http://a/y> (["etag"])` yields two
+/// lists for two resources. `None` when the header doesn't parse.
+pub fn parse(header: &str) -> Option<Vec<List>> {
+    let mut lists = vec![];
+    let mut resource = None;
+    let mut rest = header.trim_start();
+    while !rest.is_empty() {
+        if let Some(tagged) = rest.strip_prefix('<') {
+            let (tag, after) = tagged.split_once('>')?;
+            rest = after.trim_start();
+            // A tag only introduces lists; one with none after it is malformed.
+            if !rest.starts_with('(') {
+                return None;
+            }
+            resource = Some(tag.trim().to_string());
+            continue;
+        }
+        let mut body = rest.strip_prefix('(')?.trim_start();
+        let mut conditions = vec![];
+        loop {
+            if let Some(after) = body.strip_prefix(')') {
+                rest = after.trim_start();
+                break;
+            }
+            let not = match body.strip_prefix("Not") {
+                Some(after) => {
+                    body = after.trim_start();
+                    true
+                }
+                None => false,
+            };
+            let (state, after) = if let Some(token) = body.strip_prefix('<') {
+                let (token, after) = token.split_once('>')?;
+                (State::Token(token.trim().to_string()), after)
+            } else if let Some(etag) = body.strip_prefix('[') {
+                let (etag, after) = etag.split_once(']')?;
+                (State::ETag(etag.trim().to_string()), after)
+            } else {
+                return None;
+            };
+            conditions.push(Condition { not, state });
+            body = after.trim_start();
+        }
+        lists.push(List {
+            resource: resource.clone(),
+            conditions,
+        });
+    }
+    Some(lists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(not: bool, token: &str) -> Condition {
+        Condition {
+            not,
+            state: State::Token(token.to_string()),
+        }
+    }
+
+    #[test]
+    fn untagged_list() {
+        assert_eq!(
+            parse("(<opaquelocktoken:abc>)"),
+            Some(vec![List {
+                resource: None,
+                conditions: vec![token(false, "opaquelocktoken:abc")],
+            }])
+        );
+    }
+
+    #[test]
+    fn not_and_etag_conditions() {
+        assert_eq!(
+            parse(r#"(Not <DAV:no-lock> ["abc"])"#),
+            Some(vec![List {
+                resource: None,
+                conditions: vec![
+                    token(true, "DAV:no-lock"),
+                    Condition {
+                        not: false,
+                        state: State::ETag(r#""abc""#.to_string()),
+                    },
+                ],
+            }])
+        );
+    }
+
+    #[test]
+    fn resource_tags_carry_over() {
+        let lists = parse("<http://a/x> (<t1>) (<t2>) <http://a/y> (<t3>)").unwrap();
+        let resources = lists
+            .iter()
+            .map(|list| list.resource.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            resources,
+            vec![Some("http://a/x"), Some("http://a/x"), Some("http://a/y")]
+        );
+        assert_eq!(lists[2].conditions, vec![token(false, "t3")]);
+    }
+
+    #[test]
+    fn malformed_headers() {
+        assert_eq!(parse("<opaquelocktoken:abc>"), None);
+        assert_eq!(parse("<http://a/x> <http://a/y> (<t1>)"), None);
+        assert_eq!(parse("(<t1>"), None);
+        assert_eq!(parse("(<t1)"), None);
+        assert_eq!(parse(r#"(["abc")"#), None);
+        assert_eq!(parse("token"), None);
+    }
+}
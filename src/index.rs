@@ -0,0 +1,197 @@
+use serde_json::json;
+use worker::{Object, Url};
+
+use crate::dav;
+use crate::path::{decode, href};
+use crate::xml::escape;
+
+/// Orders a listing by `?sort=name|size|date` and `?order=asc|desc`, name
+/// ascending by default. Folders stay ahead of files and, having no size or
+/// date of their own, are ordered by name in the requested direction.
+pub fn sort(url: &Url, folders: &mut [String], files: &mut [Object]) {
+    let (by, descending) = order(url);
+    match by {
+        SortKey::Size => files.sort_by_key(|f| f.size()),
+        SortKey::Date => files.sort_by_key(|f| f.uploaded().as_millis()),
+        SortKey::Name => files.sort_by_key(|f| f.key()),
+    }
+    folders.sort();
+    if descending {
+        files.reverse();
+        folders.reverse();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum SortKey {
+    Name,
+    Size,
+    Date,
+}
+
+/// The key and direction asked for by `?sort=` and `?order=`.
+fn order(url: &Url) -> (SortKey, bool) {
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    };
+    let by = match param("sort").as_deref() {
+        Some("size") => SortKey::Size,
+        Some("date") => SortKey::Date,
+        _ => SortKey::Name,
+    };
+    (by, param("order").as_deref() == Some("desc"))
+}
+
+/// The listing page used unless `LISTING_TEMPLATE` replaces it.
+const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html><html><head><meta charset="utf-8"><title>Index of {{path}}</title></head><body><h1>Index of {{path}}</h1><ul>{{entries}}</ul></body></html>"#;
+
+/// Renders the browser-facing listing of a collection at `path` into
+/// `template`, or the built-in page: `{{path}}` becomes the escaped path and
+/// `{{entries}}` the `<li>` items.
+pub fn render(path: &str, folders: &[String], files: &[Object], template: Option<&str>) -> String {
+    let mut entries = String::new();
+    if path != "/" {
+        entries.push_str(r#"<li><a href="../">../</a></li>"#);
+    }
+    for folder in folders {
+        let name = decode(folder.rsplit('/').next().unwrap_or(folder));
+        entries.push_str(&format!(
+            r#"<li><a href="{}/">{}/</a></li>"#,
+            escape(&href(folder)),
+            escape(&name)
+        ));
+    }
+    for file in files {
+        let key = file.key();
+        let name = decode(key.rsplit('/').next().unwrap_or(&key));
+        entries.push_str(&format!(
+            r#"<li><a href="{}">{}</a> {} bytes</li>"#,
+            escape(&href(&key)),
+            escape(&name),
+            file.size()
+        ));
+    }
+    fill(
+        template.unwrap_or(DEFAULT_TEMPLATE),
+        &[("path", &escape(path)), ("entries", &entries)],
+    )
+}
+
+/// Replaces each `{{name}}` in one pass, so a file named `{{path}}` is not
+/// expanded again. Unknown placeholders are kept as written.
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut page = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        page.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after.split_once("}}").and_then(|(name, tail)| {
+            values
+                .iter()
+                .find(|(n, _)| *n == name.trim())
+                .map(|(_, value)| (*value, tail))
+        });
+        match value {
+            Some((value, tail)) => {
+                page.push_str(value);
+                rest = tail;
+            }
+            None => {
+                page.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    page.push_str(rest);
+    page
+}
+
+/// The same listing for scripts that ask for `application/json`.
+pub fn render_json(path: &str, folders: &[String], files: &[Object]) -> String {
+    let folders = folders
+        .iter()
+        .map(|folder| {
+            json!({
+                "name": decode(folder.rsplit('/').next().unwrap_or(folder)),
+                "href": format!("{}/", href(folder)),
+            })
+        })
+        .collect::<Vec<_>>();
+    let files = files
+        .iter()
+        .map(|file| {
+            let key = file.key();
+            json!({
+                "name": decode(key.rsplit('/').next().unwrap_or(&key)),
+                "href": href(&key),
+                "size": file.size(),
+                "etag": file.http_etag(),
+                "last_modified": dav::last_modified_of(file),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "path": path, "folders": folders, "files": files }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(query: &str) -> Url {
+        Url::parse(&format!("https://dav.example.com/docs/{}", query)).unwrap()
+    }
+
+    #[test]
+    fn order_reads_each_sort_key() {
+        assert_eq!(order(&listing("")), (SortKey::Name, false));
+        assert_eq!(order(&listing("?sort=name")), (SortKey::Name, false));
+        assert_eq!(order(&listing("?sort=size")), (SortKey::Size, false));
+        assert_eq!(
+            order(&listing("?sort=date&order=desc")),
+            (SortKey::Date, true)
+        );
+        assert_eq!(
+            order(&listing("?sort=owner&order=up")),
+            (SortKey::Name, false)
+        );
+    }
+
+    #[test]
+    fn folders_follow_the_direction_by_name() {
+        for (query, expected) in [
+            ("?sort=size", ["a", "b", "c"]),
+            ("?sort=date&order=desc", ["c", "b", "a"]),
+        ] {
+            let mut folders = ["b", "c", "a"].map(String::from);
+            sort(&listing(query), &mut folders, &mut []);
+            assert_eq!(folders, expected);
+        }
+    }
+
+    const VALUES: &[(&str, &str)] = &[("path", "/docs/"), ("entries", "<li>a</li>")];
+
+    #[test]
+    fn fills_placeholders() {
+        assert_eq!(
+            fill("<h1>{{path}}</h1><ul>{{ entries }}</ul>", VALUES),
+            "<h1>/docs/</h1><ul><li>a</li></ul>"
+        );
+    }
+
+    #[test]
+    fn values_are_not_expanded_again() {
+        assert_eq!(
+            fill("{{entries}}", &[("path", "/"), ("entries", "{{path}}")]),
+            "{{path}}"
+        );
+    }
+
+    #[test]
+    fn unknown_and_unterminated_placeholders_stay() {
+        assert_eq!(fill("{{title}} {{path}}", VALUES), "{{title}} /docs/");
+        assert_eq!(fill("{{path", VALUES), "{{path");
+        assert_eq!(fill("no placeholders", VALUES), "no placeholders");
+    }
+}
@@ -1,12 +1,37 @@
-use base64::{engine::general_purpose, Engine as _};
 use http::Method;
-use std::{collections::HashMap, future::Future, pin::Pin};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use worker::*;
 
+use crate::auth::{basic_credentials, CHALLENGE};
+use crate::config::{
+    is_apple_double, kept_for, Config, BUCKET_BINDING, BULK_DELETE_KEY, IMPORT_KEY, INFO_KEY,
+    INTERNAL_PREFIX, SIGN_KEY, TRASH_PREFIX, VERSIONS_PREFIX,
+};
 use crate::constant::*;
 use crate::dav::DavBuilder;
+use crate::error::error_response;
+use crate::path::normalize_key;
+use crate::range::RangeRequest;
+use crate::retry::retry;
+use crate::timeout::with_deadline;
+mod auth;
+mod config;
 mod constant;
 mod dav;
+mod error;
+mod etag;
+mod if_header;
+mod index;
+mod lock;
+mod path;
+mod range;
+mod retry;
+mod s3;
+mod share;
+mod sniff;
+mod timeout;
+mod xml;
+mod zip;
 
 /// [DAV header RFC](http://www.webdav.org/specs/rfc4918.html#HEADER_DAV)
 
@@ -16,296 +41,2654 @@ async fn main(req: Request, env: Env, _ctx: Context) -> Result<Response> {
         env.var("USERNAME")?.to_string(),
         env.var("PASSWORD")?.to_string(),
     );
-    let b64 = general_purpose::STANDARD.encode(format!("{}:{}", username, password));
-    let bucket = env.bucket("bucket")?;
+    let bucket = env.bucket(BUCKET_BINDING)?;
+    let config = Config::from_env(&env);
+    retry::set_attempts(config.retry_attempts);
+    path::set_mount(&config.mount_path);
+
+    let origin = config.cors_origin(req.headers().get("Origin")?);
+    // Browsers send preflights for every non-simple DAV method and never attach
+    // credentials to them, so they have to be answered before the auth check.
+    if req.method().as_str() == "OPTIONS"
+        && req
+            .headers()
+            .get("Access-Control-Request-Method")?
+            .is_some()
+    {
+        return with_cors(Response::empty()?.with_status(204), origin, &config);
+    }
+
+    // Send every other host name of the deployment to the canonical one. Only
+    // GET and HEAD may be turned into a new GET; 308 keeps any other method
+    // and its body.
+    if let Some(host) = &config.canonical_host {
+        let mut url = req.url()?;
+        if !url.host_str().is_some_and(|h| h.eq_ignore_ascii_case(host)) {
+            url.set_host(Some(host))
+                .map_err(|_| "Invalid CANONICAL_HOST")?;
+            let status = match req.method().as_str() {
+                "GET" | "HEAD" => 301,
+                _ => 308,
+            };
+            return Response::redirect_with_status(url, status);
+        }
+    }
+
+    // Browsers and crawlers ask for these on their own; answering them
+    // without credentials spares users a login prompt for a request they
+    // never made.
+    if config.public_assets && matches!(req.method().as_str(), "GET" | "HEAD") {
+        match req.path().as_str() {
+            "/favicon.ico" => return with_cors(empty_response(204)?, origin, &config),
+            "/robots.txt" => {
+                let mut headers = Headers::new();
+                headers.append("Content-Type", "text/plain; charset=utf-8")?;
+                let res = Response::ok(config.robots_txt.clone())?.with_headers(headers);
+                return with_cors(res, origin, &config);
+            }
+            _ => {}
+        }
+    }
 
     // Ensure to return Ok, even if the http header is not set
-    match req.headers().get("Authorization") {
-        Ok(Some(auth)) if auth == format!("Basic {}", b64) => {
-            let origin = req.headers().get("Origin")?.or(Some(String::from("*")));
-            dispatch_request(req, bucket)
-                .await?
-                .with_cors(&set_cors_headers(origin))
+    let credentials = req
+        .headers()
+        .get("Authorization")
+        .ok()
+        .flatten()
+        .and_then(|auth| basic_credentials(&auth));
+    // The admin signs in like any user.
+    let authenticated = credentials
+        .as_ref()
+        .is_some_and(|(user, pass)| *user == username && *pass == password)
+        || config.is_admin(credentials.as_ref());
+    // A share link stands in for credentials, but only to read what it names.
+    let share_link = match &config.signing_secret {
+        Some(secret) if matches!(req.method().as_str(), "GET" | "HEAD") => {
+            share::verify(secret, &req.url()?)
+        }
+        _ => None,
+    };
+    if !authenticated && share_link == Some(false) {
+        let res = Response::error("Forbidden: invalid or expired link", 403)?;
+        return with_cors(res, origin, &config);
+    }
+    if !authenticated && share_link != Some(true) {
+        let mut headers = Headers::new();
+        headers.append("WWW-Authenticate", CHALLENGE)?;
+        return Ok(Response::error("Unauthorized", 401)?.with_headers(headers));
+    }
+
+    if let Some(secs) = retry::open_for() {
+        let mut headers = Headers::new();
+        headers.append("Retry-After", &secs.to_string())?;
+        let res = Response::error("Service Unavailable: storage is failing", 503)?;
+        return with_cors(res.with_headers(headers), origin, &config);
+    }
+
+    // Give up before the runtime kills the request so the client gets
+    // a meaningful status instead of a dropped connection. Writes are never
+    // cut short: an upload would fail at its end, and a folder DELETE, COPY
+    // or MOVE would be left half done.
+    let writes = [
+        "PUT",
+        "POST",
+        "DELETE",
+        "COPY",
+        "MOVE",
+        "PROPPATCH",
+        "MKCOL",
+        "BIND",
+        "UNBIND",
+    ];
+    let timeout = if writes.contains(&req.method().as_str()) {
+        0
+    } else {
+        config.request_timeout_ms
+    };
+    let dispatch = dispatch_request(req, bucket, config.clone());
+    let res = match with_deadline(dispatch, timeout).await {
+        Some(res) => res.or_else(|e| error_response(&e))?,
+        None => Response::error("Gateway Timeout: storage did not respond in time", 504)?,
+    };
+    with_cors(res, origin, &config)
+}
+
+/// The number of listed files and their total size, for the root's
+/// `object-count` and `total-bytes`. Counting lists the whole bucket, so the
+/// result is kept in the edge cache for `ROOT_USAGE_TTL` seconds.
+async fn root_usage(bucket: &Bucket, url: &Url, config: &Config) -> Result<(usize, u64)> {
+    let cache = Cache::default();
+    let mut cache_url = url.clone();
+    cache_url.set_path(&format!("/{}/usage", INTERNAL_PREFIX));
+    cache_url.set_query(None);
+    if let Some(mut cached) = cache.get(cache_url.as_str(), false).await? {
+        let usage: serde_json::Value = serde_json::from_str(&cached.text().await?)?;
+        if let (Some(count), Some(bytes)) = (usage["count"].as_u64(), usage["bytes"].as_u64()) {
+            return Ok((count as usize, bytes));
+        }
+    }
+
+    // Folded page by page, so a bucket of any size is counted in constant
+    // memory.
+    let mut usage = (0, 0);
+    let mut cursor = None;
+    loop {
+        let page = list_page(bucket, "", config.list_page_size, cursor).await?;
+        for object in page.objects() {
+            if !object.key().ends_with('/') && config.is_listed(&object.key()) {
+                usage.0 += 1;
+                usage.1 += object.size() as u64;
+            }
+        }
+        if !page.truncated() {
+            break;
+        }
+        cursor = page.cursor();
+    }
+    let mut headers = Headers::new();
+    headers.set("Cache-Control", &format!("max-age={}", ROOT_USAGE_TTL))?;
+    let body = serde_json::json!({ "count": usage.0, "bytes": usage.1 });
+    cache
+        .put(
+            cache_url.as_str(),
+            Response::ok(body.to_string())?.with_headers(headers),
+        )
+        .await?;
+    Ok(usage)
+}
+
+/// Pages through everything below `prefix`, `LIST_PAGE_SIZE` keys per round
+/// trip, for the writes that have to see a whole folder. Holding more than
+/// `MAX_LISTED_OBJECTS` would run the isolate out of memory, so the listing
+/// stops there and callers refuse rather than act on part of a folder.
+async fn list_all_files(
+    bucket: &Bucket,
+    prefix: impl Into<String> + Copy,
+    config: &Config,
+) -> Result<Listing> {
+    list_files_up_to(
+        bucket,
+        prefix,
+        config.list_page_size,
+        config.max_listed_objects,
+        None,
+    )
+    .await
+}
+
+/// A listing that may have been cut short.
+struct Listing {
+    objects: Vec<Object>,
+    /// More than `limit` objects were found and the rest left unlisted.
+    truncated: bool,
+}
+
+/// Pages through everything below `prefix`, `page_size` keys per round trip
+/// (at most R2's 1000), until more than `limit` objects are held or the
+/// clock passes `deadline` (ms since the epoch). The listings that answer
+/// with every object at once (PROPFIND, SEARCH, ZIP) set both, as they would
+/// otherwise run the isolate out of memory or time.
+async fn list_files_up_to(
+    bucket: &Bucket,
+    prefix: impl Into<String> + Copy,
+    page_size: u32,
+    limit: usize,
+    deadline: Option<u64>,
+) -> Result<Listing> {
+    let prefix: String = prefix.into();
+    let mut files = vec![];
+    let mut cursor = None;
+    loop {
+        let objects = list_page(bucket, &prefix, page_size, cursor).await?;
+        files.extend(objects.objects());
+        if files.len() > limit || deadline.is_some_and(|d| Date::now().as_millis() > d) {
+            return Ok(Listing {
+                objects: files,
+                truncated: true,
+            });
+        }
+        if !objects.truncated() {
+            break;
+        }
+        cursor = objects.cursor();
+    }
+    Ok(Listing {
+        objects: files,
+        truncated: false,
+    })
+}
+
+/// One round trip of a listing below `prefix`, metadata included, picking up
+/// where `cursor` left off.
+async fn list_page(
+    bucket: &Bucket,
+    prefix: &str,
+    page_size: u32,
+    cursor: Option<String>,
+) -> Result<Objects> {
+    retry(|| {
+        let mut list_req = bucket
+            .list()
+            .include(vec![Include::HttpMetadata, Include::CustomMetadata])
+            .limit(page_size);
+        if !prefix.is_empty() {
+            list_req = list_req.prefix(prefix);
+        }
+        if let Some(c) = cursor.clone() {
+            list_req = list_req.cursor(c);
+        }
+        list_req.execute()
+    })
+    .await
+}
+
+/// Listing prefix for the members of a collection; the root has none.
+fn collection_prefix(key: &str) -> String {
+    if key.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", key)
+    }
+}
+
+/// One level of a collection: the objects directly in it, its own marker
+/// included, and its sub-collections, which R2 reports as delimited prefixes
+/// instead of every key below them. A folder with ten subfolders of 10,000
+/// files each is listed in one round trip rather than a hundred, and `a/b/c`
+/// and `a/d` below the root collapse into the one folder `a`.
+async fn list_level(
+    bucket: &Bucket,
+    prefix: &str,
+    page_size: u32,
+) -> Result<(Vec<Object>, Vec<String>)> {
+    let (mut objects, mut folders) = (vec![], vec![]);
+    let mut cursor = None;
+    loop {
+        let (page, page_folders, next_cursor) =
+            list_level_page(bucket, prefix, page_size, cursor).await?;
+        objects.extend(page);
+        folders.extend(page_folders);
+        match next_cursor {
+            Some(next_cursor) => cursor = Some(next_cursor),
+            None => break,
+        }
+    }
+    // A prefix can come back on more than one page; list it once.
+    folders.sort();
+    folders.dedup();
+    Ok((objects, folders))
+}
+
+/// A single page of `list_level`, with folders as keys without the trailing
+/// `/`, plus the cursor of the next page when truncated.
+async fn list_level_page(
+    bucket: &Bucket,
+    prefix: &str,
+    limit: u32,
+    cursor: Option<String>,
+) -> Result<(Vec<Object>, Vec<String>, Option<String>)> {
+    let objects = retry(|| {
+        let mut list_req = bucket
+            .list()
+            .include(vec![Include::HttpMetadata, Include::CustomMetadata])
+            .delimiter("/")
+            .limit(limit);
+        if !prefix.is_empty() {
+            list_req = list_req.prefix(prefix);
+        }
+        if let Some(c) = cursor.clone() {
+            list_req = list_req.cursor(c);
+        }
+        list_req.execute()
+    })
+    .await?;
+    // Only the one segment after `prefix` makes a folder. A doubled slash,
+    // as in `a//b` below `a/`, names a segment that is empty and no folder.
+    let folders = objects
+        .delimited_prefixes()
+        .into_iter()
+        .map(|folder| folder.strip_suffix('/').unwrap_or(&folder).to_string())
+        .filter(|folder| folder.len() > prefix.len())
+        .collect();
+    let next_cursor = if objects.truncated() {
+        objects.cursor()
+    } else {
+        None
+    };
+    Ok((objects.objects(), folders, next_cursor))
+}
+
+/// `?limit=N&cursor=...` opts a PROPFIND into paging. Files and
+/// sub-collections both count towards the limit.
+fn page_params(url: &Url) -> Option<(u32, Option<String>)> {
+    let limit = url
+        .query_pairs()
+        .find(|(k, _)| k == "limit")?
+        .1
+        .parse::<u32>()
+        .ok()?;
+    let cursor = url
+        .query_pairs()
+        .find(|(k, _)| k == "cursor")
+        .map(|(_, v)| v.into_owned());
+    Some((limit.clamp(1, 1000), cursor))
+}
+
+/// The visible immediate children of a collection: sub-collection keys and
+/// files. The collection's own `/` marker is not a child.
+async fn list_children(
+    bucket: &Bucket,
+    key: &str,
+    config: &Config,
+) -> Result<(Vec<String>, Vec<Object>)> {
+    let prefix = collection_prefix(key);
+    let (objects, folders) = list_level(bucket, &prefix, config.list_page_size).await?;
+    let folders = folders
+        .into_iter()
+        .filter(|folder| config.is_listed_in(key, folder))
+        .collect();
+    let files = objects
+        .into_iter()
+        .filter(|object| {
+            let member = object.key();
+            member.len() > prefix.len() && config.is_listed_in(key, &member)
+        })
+        .collect();
+    Ok((folders, files))
+}
+
+/// The key of the file a request path names: the canonical key, unless only
+/// the spelling it was stored under before keys were canonical exists. Reads,
+/// DELETE and COPY or MOVE sources go through it, so such files can still be
+/// fetched, removed or moved to their canonical name.
+async fn stored_key(bucket: &Bucket, url: &Url) -> Result<String> {
+    let key = path::key_of(url.path());
+    if let Some(legacy) = path::legacy_key_of(url.path()) {
+        if retry(|| bucket.head(&key)).await?.is_none()
+            && retry(|| bucket.head(&legacy)).await?.is_some()
+        {
+            return Ok(legacy);
+        }
+    }
+    Ok(key)
+}
+
+/// A collection either has its `/` marker or at least one object below it.
+async fn is_collection(bucket: &Bucket, key: &str) -> Result<bool> {
+    let prefix = format!("{}/", key);
+    if retry(|| bucket.head(&prefix)).await?.is_some() {
+        return Ok(true);
+    }
+    let objects = retry(|| bucket.list().prefix(prefix.clone()).limit(1).execute()).await?;
+    Ok(!objects.objects().is_empty())
+}
+
+/// [Not advertised in OPTIONS response](http://www.webdav.org/specs/rfc4918.html#HEADER_DAV)
+async fn handle_options(_req: Request, _bucket: Bucket, config: Config) -> Result<Response> {
+    let mut headers = Headers::new();
+    let mut methods = METHODS.to_vec();
+    if !config.bind {
+        methods.retain(|m| !matches!(*m, "BIND" | "UNBIND"));
+    }
+    if config.versioning {
+        methods.push("VERSION-CONTROL");
+    }
+    headers.append("DAV", &config.dav_classes().join(", "))?;
+    headers.append("DASL", "<DAV:basicsearch>")?;
+    headers.append("Allow", methods.join(", ").as_str())?;
+    Ok(Response::empty()?.with_status(204).with_headers(headers))
+}
+
+/// [HEAD method](http://www.webdav.org/specs/rfc4918.html#n-get--head-for-collections)
+async fn handle_head(req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    let res = handle_get(req, bucket, config).await?;
+    Ok(Response::empty()?
+        .with_status(res.status_code())
+        .with_headers(res.headers().clone()))
+}
+
+/// Object bodies are handed to the runtime as R2's `ReadableStream` and
+/// never read in Rust, so a multi-GB download pulls chunks only as fast as
+/// the client takes them and the isolate's memory stays flat. Only the edge
+/// cache, the fallback store and `get_byteranges` buffer a body, each below
+/// its size limit; a multipart range response holds up to 16 MiB.
+async fn handle_get(req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    let url = req.url()?;
+    // `?content-type=text/plain` shows a file inline as that type, which
+    // helps debugging. Types outside `CONTENT_TYPE_OVERRIDES` are ignored.
+    let content_type = url
+        .query_pairs()
+        .find(|(k, _)| k == "content-type")
+        .map(|(_, v)| v.trim().to_lowercase())
+        .filter(|t| CONTENT_TYPE_OVERRIDES.contains(&t.as_str()));
+    let mut res = get_resource(req, bucket, config).await?;
+    // A multi-range reply keeps its `multipart/byteranges` type, which
+    // carries the boundary its parts are read by; each part keeps its own.
+    let multipart = res
+        .headers()
+        .get("Content-Type")?
+        .is_some_and(|t| t.starts_with("multipart/"));
+    if let Some(content_type) = content_type.filter(|_| !url.path().ends_with('/')) {
+        if matches!(res.status_code(), 200 | 206) && !multipart {
+            res.headers_mut().set("Content-Type", &content_type)?;
+            res.headers_mut().set("X-Content-Type-Options", "nosniff")?;
+        }
+    }
+    Ok(as_stored(res))
+}
+
+async fn get_resource(req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    let url = req.url()?;
+    let key = &stored_key(&bucket, &url).await?;
+    if key == INFO_KEY {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "application/json")?;
+        headers.append("Cache-Control", "no-store")?;
+        return Ok(Response::ok(config.describe().to_string())?.with_headers(headers));
+    }
+    if key == SIGN_KEY {
+        return handle_sign(&url, &bucket, &config).await;
+    }
+    // What the trash and the versions keep can be read before it's restored.
+    if config.is_hidden(key) && !config.is_kept(key) {
+        return Response::error("Not Found", 404);
+    }
+    if config.is_write_only(key) {
+        return write_only_response();
+    }
+    // The root is a collection; send clients to its canonical `/` form
+    // instead of looking up an object with an empty key.
+    if key.is_empty() && !url.path().ends_with('/') {
+        let mut target = url.clone();
+        target.set_path(&format!("{}/", url.path()));
+        return Response::redirect_with_status(target, 301);
+    }
+    // A generated listing is always sent whole; `Range` only ever applies to
+    // stored objects, an index document included.
+    if url.path().ends_with('/') {
+        if url.query_pairs().any(|(k, _)| k == "zip") {
+            return get_zip(bucket, key, &config).await;
+        }
+        // A collection with an index document serves that instead of a listing.
+        if let Some(index) = &config.index_document {
+            let index_key = format!("{}{}", collection_prefix(key), index);
+            if retry(|| bucket.head(&index_key)).await?.is_some() {
+                return get_object(&req, &bucket, &index_key, &config).await;
+            }
+        }
+        let wants_json = req
+            .headers()
+            .get("Accept")?
+            .is_some_and(|accept| accept.contains("application/json"));
+        let mut headers = Headers::new();
+        // The body depends on `Accept`, and the edge compresses by `Accept-Encoding`.
+        headers.append("Vary", "Accept, Accept-Encoding")?;
+        // Generated bodies are never served in parts.
+        headers.append("Accept-Ranges", "none")?;
+        let (mut folders, mut files) = list_children(&bucket, key, &config).await?;
+        if folders.is_empty()
+            && files.is_empty()
+            && !key.is_empty()
+            && !is_collection(&bucket, key).await?
+        {
+            let page = r#"<!DOCTYPE HTML PUBLIC "-//IETF//DTD HTML 2.0//EN"><html><head><title>404 Not Found</title></head><body><h1>Not Found</h1><p>The requested URL was not found on this server.</p></body></html>"#;
+            headers.append("Content-Type", "text/html; charset=utf-8")?;
+            return Ok(Response::ok(page)?.with_status(404).with_headers(headers));
+        }
+        index::sort(&url, &mut folders, &mut files);
+        if wants_json {
+            headers.append("Content-Type", "application/json")?;
+            let body = index::render_json(url.path(), &folders, &files);
+            return Ok(Response::ok(body)?.with_headers(headers));
+        }
+        headers.append("Content-Type", "text/html; charset=utf-8")?;
+        let template = listing_template(&bucket, &config).await?;
+        let page = index::render(url.path(), &folders, &files, template.as_deref());
+        return Ok(Response::ok(page)?.with_headers(headers));
+    }
+
+    let target = match retry(|| bucket.head(key)).await? {
+        Some(object) => match bind_target(&object) {
+            // A binding opens no more than its target would on its own.
+            Some(target) if config.is_hidden(&target) => {
+                return Response::error("Not Found", 404);
+            }
+            Some(target) if config.is_write_only(&target) => return write_only_response(),
+            Some(target) => target,
+            None => key.to_string(),
+        },
+        None => key.to_string(),
+    };
+    get_object(&req, &bucket, &target, &config).await
+}
+
+/// Serves the stored object at `key`, honoring `If-None-Match`, `Range` and
+/// `If-Range`, whether it was asked for by name or as a collection's index
+/// document.
+async fn get_object(
+    req: &Request,
+    bucket: &Bucket,
+    key: &str,
+    config: &Config,
+) -> Result<Response> {
+    // A client whose copy is current gets a `304`. The comparison is weak,
+    // as for any `If-None-Match`.
+    if let Some(if_none_match) = req.headers().get("If-None-Match")? {
+        if let Some(object) = retry(|| bucket.head(key)).await? {
+            if etag::weak_match(&if_none_match, &object.http_etag()) {
+                let headers = object_headers(bucket, key, &object, config).await?;
+                return Ok(Response::empty()?.with_status(304).with_headers(headers));
+            }
+        }
+    }
+
+    let range = match req.headers().get("Range")? {
+        Some(header) => {
+            let object = match retry(|| bucket.head(key)).await? {
+                Some(object) => object,
+                None => return origin_fallback(req, bucket, key, config).await,
+            };
+            let size = object.size() as u64;
+            let current = match req.headers().get("If-Range")? {
+                Some(if_range) => range::if_range_matches(&if_range, &object.http_etag()),
+                None => true,
+            };
+            match range::parse(&header, size) {
+                _ if !current => None,
+                RangeRequest::Partial(range) => Some((range, size)),
+                RangeRequest::Multiple(ranges) => {
+                    return get_byteranges(bucket, key, &object, &ranges, config).await;
+                }
+                RangeRequest::Unsatisfiable => {
+                    let mut headers = Headers::new();
+                    headers.append("Content-Range", &format!("bytes */{}", size))?;
+                    return Ok(Response::error("Range Not Satisfiable", 416)?.with_headers(headers));
+                }
+                RangeRequest::Full => None,
+            }
+        }
+        None => None,
+    };
+
+    let (range, size) = match range {
+        Some(range) => range,
+        None => {
+            if config.serve_precompressed {
+                if let Some(res) = get_precompressed(req, bucket, key, config).await? {
+                    return Ok(res);
+                }
+            }
+            // A HEAD throws the body away, so it isn't worth buffering one
+            // into the cache for.
+            if config.edge_cache && req.method().as_str() != "HEAD" {
+                if let Some(res) = get_cached(bucket, &req.url()?, key, config).await? {
+                    return Ok(res);
+                }
+            }
+            let object = match retry(|| bucket.get(key).execute()).await? {
+                Some(object) => object,
+                None => return origin_fallback(req, bucket, key, config).await,
+            };
+            let headers = object_headers(bucket, key, &object, config).await?;
+            let stream = object.body().ok_or("Body is None")?.stream()?;
+            return Ok(Response::from_stream(stream)?.with_headers(headers));
+        }
+    };
+    let object = bucket
+        .get(key)
+        .range(Range::OffsetWithLength {
+            offset: range.start,
+            length: range.length(),
+        })
+        .execute()
+        .await?
+        .ok_or("Object is None")?;
+    let mut headers = object_headers(bucket, key, &object, config).await?;
+    headers.set("Content-Range", &range.content_range(size))?;
+    headers.set("Content-Length", &range.length().to_string())?;
+    let stream = object.body().ok_or("Body is None")?.stream()?;
+    Ok(Response::from_stream(stream)?
+        .with_status(206)
+        .with_headers(headers))
+}
+
+/// Answers a `Range` of several slices with one `multipart/byteranges` body,
+/// each part read from R2 on its own and labelled with its `Content-Range`.
+async fn get_byteranges(
+    bucket: &Bucket,
+    key: &str,
+    object: &Object,
+    ranges: &[range::ByteRange],
+    config: &Config,
+) -> Result<Response> {
+    let size = object.size() as u64;
+    let mut headers = object_headers(bucket, key, object, config).await?;
+    let content_type = headers
+        .get("Content-Type")?
+        .unwrap_or_else(|| String::from("application/octet-stream"));
+    let boundary = uuid::Uuid::new_v4().simple().to_string();
+    let mut body = vec![];
+    for range in ranges {
+        let part = retry(|| {
+            bucket
+                .get(key)
+                .range(Range::OffsetWithLength {
+                    offset: range.start,
+                    length: range.length(),
+                })
+                .execute()
+        })
+        .await?
+        .ok_or("Object is None")?;
+        body.extend_from_slice(
+            format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                boundary,
+                content_type,
+                range.content_range(size)
+            )
+            .as_bytes(),
+        );
+        body.extend(part.body().ok_or("Body is None")?.bytes().await?);
+    }
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    headers.set(
+        "Content-Type",
+        &format!("multipart/byteranges; boundary={}", boundary),
+    )?;
+    Ok(Response::from_bytes(body)?
+        .with_status(206)
+        .with_headers(headers))
+}
+
+/// The operator's listing page: the `LISTING_TEMPLATE_KEY` object while it
+/// exists, so it can be restyled without a deploy, else `LISTING_TEMPLATE`.
+async fn listing_template(bucket: &Bucket, config: &Config) -> Result<Option<String>> {
+    if let Some(template_key) = &config.listing_template_key {
+        if let Some(object) = retry(|| bucket.get(template_key).execute()).await? {
+            let body = object.body().ok_or("Body is None")?;
+            return Ok(Some(body.text().await?));
+        }
+    }
+    Ok(config.listing_template.clone())
+}
+
+/// `GET /.dav/sign?key=docs/a.pdf&expires=3600` mints a link to `key` that
+/// works without credentials for `expires` seconds (an hour by default, a
+/// week at most), answered as `{ "url": ..., "expires": ... }` with the
+/// expiry in seconds since the epoch.
+async fn handle_sign(url: &Url, bucket: &Bucket, config: &Config) -> Result<Response> {
+    let secret = match &config.signing_secret {
+        Some(secret) => secret,
+        None => return Response::error("Not Found", 404),
+    };
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    };
+    let key = normalize_key(&param("key").unwrap_or_default());
+    if key.is_empty() || config.is_hidden(&key) || config.is_write_only(&key) {
+        return Response::error("Bad Request: expected the key of a file", 400);
+    }
+    // Only files get links; a collection would hand out its whole listing.
+    if retry(|| bucket.head(&key)).await?.is_none() {
+        return Response::error("Not Found: no such file", 404);
+    }
+    let lifetime = match param("expires") {
+        Some(secs) => match secs.parse::<u64>() {
+            Ok(secs) if secs > 0 => secs.min(share::MAX_EXPIRES_SECS),
+            _ => return Response::error("Bad Request: expires must be a number of seconds", 400),
+        },
+        None => share::DEFAULT_EXPIRES_SECS,
+    };
+    let expires = Date::now().as_millis() / 1000 + lifetime;
+    let mut link = url.clone();
+    link.set_path(&path::href(&key));
+    share::sign(secret, &mut link, expires);
+
+    let mut headers = Headers::new();
+    headers.append("Content-Type", "application/json")?;
+    headers.append("Cache-Control", "no-store")?;
+    let body = serde_json::json!({ "url": link.as_str(), "expires": expires });
+    Ok(Response::ok(body.to_string())?.with_headers(headers))
+}
+
+/// `GET folder/?zip` downloads everything listed below `folder/` as one
+/// archive, streamed entry by entry with paths relative to the folder.
+async fn get_zip(bucket: Bucket, key: &str, config: &Config) -> Result<Response> {
+    let prefix = collection_prefix(key);
+    let listing = list_files_up_to(
+        &bucket,
+        prefix.as_str(),
+        config.list_page_size,
+        config.max_listed_objects,
+        None,
+    )
+    .await?;
+    if listing.truncated {
+        return Response::error("Insufficient Storage: folder holds too many files", 507);
+    }
+    let objects = listing.objects;
+    if objects.is_empty() && !key.is_empty() {
+        return Response::error("Not Found", 404);
+    }
+    // Names are relative to the collection, so `a/b.txt` stays below `a/`.
+    // A key that would extract outside the target folder is left out.
+    let mut entries = vec![];
+    for object in &objects {
+        let relative = &object.key()[prefix.len()..];
+        if relative.is_empty() || !config.is_listed_in(key, &object.key()) {
+            continue;
+        }
+        if let Some(name) = zip::entry_name(relative) {
+            entries.push(zip::Entry {
+                key: object.key(),
+                name,
+                size: object.size() as u64,
+                modified: dav::uploaded(object),
+            });
+        }
+    }
+    let entries = zip::with_folders(entries);
+    if !zip::fits(&entries) {
+        return Response::error(
+            "Payload Too Large: folder exceeds 4 GiB or 65535 files",
+            413,
+        );
+    }
+
+    let name = key
+        .rsplit('/')
+        .next()
+        .filter(|n| !n.is_empty())
+        .unwrap_or("archive");
+    // `filename` is an ASCII stand-in for clients that don't read the
+    // RFC 8187 `filename*` with the real name.
+    let name = path::decode(name);
+    let fallback = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let encoded = name
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect::<String>();
+    let mut headers = Headers::new();
+    headers.append("Content-Type", "application/zip")?;
+    headers.append(
+        "Content-Disposition",
+        &format!(
+            "attachment; filename=\"{}.zip\"; filename*=UTF-8''{}.zip",
+            fallback, encoded
+        ),
+    )?;
+    headers.append("Accept-Ranges", "none")?;
+    let stream = zip::stream(bucket, entries);
+    Ok(Response::from_stream(stream)?.with_headers(headers))
+}
+
+/// Upload-only folders answer reads and listings with 405, naming the
+/// methods they do take.
+fn write_only_response() -> Result<Response> {
+    let mut headers = Headers::new();
+    headers.set("Allow", "PUT, MKCOL")?;
+    Ok(Response::error("Method Not Allowed", 405)?.with_headers(headers))
+}
+
+/// Files missing from R2 are fetched from `ORIGIN_FALLBACK_URL` while
+/// migrating off a legacy origin. Full responses are copied into the bucket
+/// when `ORIGIN_FALLBACK_STORE` is on so the next read is served from R2;
+/// those without a length, or past `FALLBACK_STORE_MAX_SIZE`, are only
+/// streamed through.
+async fn origin_fallback(
+    req: &Request,
+    bucket: &Bucket,
+    key: &str,
+    config: &Config,
+) -> Result<Response> {
+    let base = match &config.origin_fallback_url {
+        Some(base) => base,
+        None => return Response::error("Not Found", 404),
+    };
+    let mut upstream_headers = Headers::new();
+    if let Some(range) = req.headers().get("Range")? {
+        upstream_headers.set("Range", &range)?;
+    }
+    let mut init = RequestInit::new();
+    init.with_headers(upstream_headers);
+    let upstream = Request::new_with_init(&format!("{}/{}", base, key), &init)?;
+    let mut res = Fetch::Request(upstream).send().await?;
+    let status = res.status_code();
+    if !matches!(status, 200 | 206) {
+        return Response::error("Not Found", 404);
+    }
+
+    // Fetched responses have immutable headers; pass on the ones that matter.
+    let mut headers = Headers::new();
+    for name in [
+        "Content-Type",
+        "Content-Length",
+        "Content-Range",
+        "ETag",
+        "Last-Modified",
+    ] {
+        if let Some(value) = res.headers().get(name)? {
+            headers.set(name, &value)?;
+        }
+    }
+    let length = res
+        .headers()
+        .get("Content-Length")?
+        .and_then(|length| length.parse::<u64>().ok());
+    let storable = length.is_some_and(|length| length <= FALLBACK_STORE_MAX_SIZE);
+    if config.origin_fallback_store && status == 200 && storable {
+        let bytes = res.bytes().await?;
+        let http_metadata = HttpMetadata {
+            content_type: headers.get("Content-Type")?,
+            ..Default::default()
+        };
+        bucket
+            .put(key, Data::from(bytes.clone()))
+            .http_metadata(http_metadata)
+            .execute()
+            .await?;
+        return Ok(Response::from_bytes(bytes)?.with_headers(headers));
+    }
+    Ok(Response::from_stream(res.stream()?)?
+        .with_status(status)
+        .with_headers(headers))
+}
+
+/// Serves `{key}.br` or `{key}.gz` when the client accepts that encoding and
+/// the sidecar exists, labelled with the original object's content type.
+async fn get_precompressed(
+    req: &Request,
+    bucket: &Bucket,
+    key: &str,
+    config: &Config,
+) -> Result<Option<Response>> {
+    let accepted = req.headers().get("Accept-Encoding")?.unwrap_or_default();
+    let accepts = |encoding: &str| {
+        accepted.split(',').any(|part| {
+            let mut params = part.split(';').map(str::trim);
+            params.next() == Some(encoding) && !params.any(|p| p == "q=0" || p == "q=0.0")
+        })
+    };
+    for (encoding, suffix) in [("br", "br"), ("gzip", "gz")] {
+        if !accepts(encoding) {
+            continue;
+        }
+        let sidecar = format!("{}.{}", key, suffix);
+        let object = match retry(|| bucket.get(&sidecar).execute()).await? {
+            Some(object) => object,
+            None => continue,
+        };
+        let original = retry(|| bucket.head(key)).await?;
+        let mut headers = object_headers(bucket, &sidecar, &object, config).await?;
+        if let Some(content_type) = original.and_then(|o| o.http_metadata().content_type) {
+            headers.set("Content-Type", &content_type)?;
+        }
+        headers.set("Content-Encoding", encoding)?;
+        headers.set("Content-Location", &path::href(key))?;
+        headers.set("Vary", "Accept-Encoding")?;
+        // Ranges would have to address the compressed bytes; keep it whole.
+        headers.set("Accept-Ranges", "none")?;
+        // The `ETag` sent is the sidecar's, so that is what a client sends
+        // back in `If-None-Match`, and what it is compared with here.
+        if let Some(if_none_match) = req.headers().get("If-None-Match")? {
+            if etag::weak_match(&if_none_match, &object.http_etag()) {
+                return Ok(Some(
+                    Response::empty()?.with_status(304).with_headers(headers),
+                ));
+            }
+        }
+        let stream = object.body().ok_or("Body is None")?.stream()?;
+        return Ok(Some(Response::from_stream(stream)?.with_headers(headers)));
+    }
+    Ok(None)
+}
+
+async fn object_headers(
+    bucket: &Bucket,
+    key: &str,
+    object: &Object,
+    config: &Config,
+) -> Result<Headers> {
+    let mut http_meta_data = object.http_metadata();
+    if http_meta_data.content_type.is_none() && config.sniff_content_type && object.size() > 0 {
+        http_meta_data.content_type = sniff_content_type(bucket, key).await?;
+    }
+    let mut headers = get_headers(http_meta_data)?;
+    headers.set("Accept-Ranges", "bytes")?;
+    // Sent as R2 reports it, `-{parts}` suffix of multipart uploads included,
+    // so it can come back in `If-Range`.
+    headers.set("ETag", &object.http_etag())?;
+    headers.set("Last-Modified", &dav::last_modified_of(object))?;
+    // Names the object actually served, which differs from the request URL
+    // when a collection resolves to its index document.
+    headers.set("Content-Location", &path::href(key))?;
+    for (name, value) in object.custom_metadata()? {
+        if name.starts_with(AMZ_META_PREFIX) {
+            headers.set(&name, &value)?;
+        }
+    }
+    Ok(headers)
+}
+
+/// R2 objects never change under the same etag, so cached copies are keyed by
+/// URL and etag and cannot go stale. Objects are buffered to fill the cache,
+/// hence the size limit.
+async fn get_cached(
+    bucket: &Bucket,
+    url: &Url,
+    key: &str,
+    config: &Config,
+) -> Result<Option<Response>> {
+    let object = match retry(|| bucket.head(key)).await? {
+        Some(object) if (object.size() as u64) <= EDGE_CACHE_MAX_SIZE => object,
+        _ => return Ok(None),
+    };
+    let cache = Cache::default();
+    let cache_key = edge_cache_key(url, &object.etag());
+    // The cached copy carries the edge TTL; clients get the object's own headers.
+    let headers = object_headers(bucket, key, &object, config).await?;
+    if let Some(res) = cache.get(cache_key.as_str(), false).await? {
+        return Ok(Some(res.with_headers(headers)));
+    }
+
+    let object = match retry(|| bucket.get(key).execute()).await? {
+        Some(object) => object,
+        None => return Ok(None),
+    };
+    let bytes = object.body().ok_or("Body is None")?.bytes().await?;
+    let mut cached_headers = headers.clone();
+    cached_headers.set(
+        "Cache-Control",
+        &format!("max-age={}", config.edge_cache_ttl),
+    )?;
+    cache
+        .put(
+            cache_key.as_str(),
+            as_stored(Response::from_bytes(bytes.clone())?.with_headers(cached_headers)),
+        )
+        .await?;
+    Ok(Some(Response::from_bytes(bytes)?.with_headers(headers)))
+}
+
+fn edge_cache_key(url: &Url, etag: &str) -> String {
+    let mut url = url.clone();
+    url.set_query(Some(&format!("etag={}", etag)));
+    url.to_string()
+}
+
+/// Drops the cached copy of the current version of `key` before it changes.
+async fn purge_cached(bucket: &Bucket, url: &Url, key: &str) -> Result<()> {
+    if let Some(object) = retry(|| bucket.head(key)).await? {
+        let url = url.join(&path::href(key)).map_err(|_| "Invalid URL")?;
+        Cache::default()
+            .delete(edge_cache_key(&url, &object.etag()).as_str(), false)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Reads the head of an object to guess a type for files stored without one.
+async fn sniff_content_type(bucket: &Bucket, key: &str) -> Result<Option<String>> {
+    let object = bucket
+        .get(key)
+        .range(Range::OffsetWithLength {
+            offset: 0,
+            length: sniff::SNIFF_LEN,
+        })
+        .execute()
+        .await?;
+    let bytes = match object.as_ref().and_then(|o| o.body()) {
+        Some(body) => body.bytes().await?,
+        None => return Ok(None),
+    };
+    Ok(sniff::sniff(&bytes).map(String::from))
+}
+
+async fn handle_delete(req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    let url = req.url()?;
+    let key = &stored_key(&bucket, &url).await?;
+
+    // The root can't go: that would take the whole bucket with it, the
+    // trash and the worker's own state included.
+    if key.is_empty() {
+        return Response::error("Forbidden", 403);
+    }
+    // What the trash or the versions keep goes for good; anything else goes
+    // to the trash while soft delete is on.
+    let trash = (config.soft_delete && kept_for(key).is_none())
+        .then(|| format!("{}/{}/", TRASH_PREFIX, Date::now().as_millis()));
+
+    if let Some(locked) = check_lock(&req, &bucket, key, true).await? {
+        return Ok(locked);
+    }
+
+    // A file goes on its own, a collection with everything below it, marker
+    // included.
+    let is_file = retry(|| bucket.head(key)).await?.is_some();
+    let keys = if is_file {
+        vec![key.to_string()]
+    } else {
+        let prefix = collection_prefix(key);
+        let listing = list_all_files(&bucket, prefix.as_str(), &config).await?;
+        if listing.truncated {
+            return Response::error("Insufficient Storage: folder holds too many files", 507);
+        }
+        listing.objects.iter().map(|f| f.key()).collect()
+    };
+    if keys.is_empty() {
+        return Response::error("Not Found", 404);
+    }
+
+    // Keep going past failures so everything that can go is gone, then
+    // report what is left.
+    let mut failed = String::new();
+    for f_key in keys {
+        let removed = async {
+            if config.edge_cache {
+                purge_cached(&bucket, &url, &f_key).await?;
+            }
+            remove_object(&bucket, &f_key, trash.as_deref()).await
+        };
+        match removed.await {
+            Ok(()) => {}
+            Err(e) if is_file => return Err(e),
+            Err(e) => {
+                console_error!("[ERROR] {}: {}", f_key, e);
+                failed.push_str(&format!(
+                    "<response><href>{}</href><status>HTTP/1.1 500 Internal Server Error</status></response>",
+                    xml::escape(&path::href(&f_key))
+                ));
+            }
+        }
+    }
+    if !failed.is_empty() {
+        return multistatus(failed, &config);
+    }
+    lock::remove_all(&bucket, key).await?;
+    Ok(Response::empty()?.with_status(204))
+}
+
+/// Deletes `key`, first copying it below `trash` when soft delete is on.
+/// PROPFIND on `.trash/` lists what was deleted, a MOVE back out of it
+/// restores a file and a DELETE in it removes the file for good.
+async fn remove_object(bucket: &Bucket, key: &str, trash: Option<&str>) -> Result<()> {
+    if let Some(trash) = trash {
+        copy_object(bucket, key, &format!("{}{}", trash, key)).await?;
+    }
+    retry(|| bucket.delete(key)).await
+}
+
+/// Permanently removes trashed objects older than `TRASH_RETENTION_DAYS`,
+/// going by the deletion timestamp in their trash key.
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    let config = Config::from_env(&env);
+    retry::set_attempts(config.retry_attempts);
+    let bucket = match env.bucket(BUCKET_BINDING) {
+        Ok(bucket) => bucket,
+        Err(e) => {
+            console_error!("[ERROR] {}", e);
+            return;
+        }
+    };
+    let cutoff = Date::now()
+        .as_millis()
+        .saturating_sub(config.trash_retention_days * 24 * 60 * 60 * 1000);
+    let prefix = format!("{}/", TRASH_PREFIX);
+    // A trash past `MAX_LISTED_OBJECTS` is purged a listing's worth at a time,
+    // the rest on the next runs.
+    let files = match list_all_files(&bucket, prefix.as_str(), &config).await {
+        Ok(listing) => listing.objects,
+        Err(e) => {
+            console_error!("[ERROR] {}", e);
+            return;
+        }
+    };
+    for f in files {
+        let deleted_at = f.key()[prefix.len()..]
+            .split('/')
+            .next()
+            .and_then(|t| t.parse::<u64>().ok());
+        if deleted_at.is_some_and(|t| t < cutoff) {
+            if let Err(e) = retry(|| bucket.delete(f.key())).await {
+                console_error!("[ERROR] {}", e);
+            }
+        }
+    }
+}
+
+/// [PROPPATCH method](http://www.webdav.org/specs/rfc4918.html#METHOD_PROPPATCH)
+///
+/// Files keep `tags` and every dead property, one outside `DAV:` and the
+/// custom namespace, in their custom metadata, and PROPFIND lists them: the
+/// Win32 timestamps Windows Explorer sets after each upload are stored as
+/// set. The `DAV:` and the other custom properties are computed from the
+/// object and refused with 403, as is anything set on a collection, which
+/// has no object to keep it on. Updates apply all or not at all, so once one
+/// is refused the others fail with 424.
+async fn handle_proppatch(mut req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    let url = req.url()?;
+    let key = &path::key_of(url.path());
+    let object = retry(|| bucket.head(key)).await?;
+    let is_file = object.is_some();
+    if !is_file && !is_collection(&bucket, key).await? {
+        return Response::error("Not Found", 404);
+    }
+    if let Some(locked) = check_lock(&req, &bucket, key, false).await? {
+        return Ok(locked);
+    }
+    let body = req.text().await?;
+    let updates = match xml::property_updates(&body) {
+        Some(updates) => updates,
+        None => return Response::error("Bad Request", 400),
+    };
+
+    let is_tags =
+        |update: &xml::Update| update.namespace == config.custom_namespace && update.name == "tags";
+    let storable = |update: &xml::Update| {
+        let live = update.namespace == "DAV:" || update.namespace == config.custom_namespace;
+        is_file && (is_tags(update) || !live)
+    };
+
+    let mut metadata = match &object {
+        Some(object) => object.custom_metadata()?,
+        None => HashMap::new(),
+    };
+    let mut props: BTreeMap<String, String> = metadata
+        .get(DEAD_PROPS_KEY)
+        .and_then(|props| serde_json::from_str(props).ok())
+        .unwrap_or_default();
+    for update in updates.iter().filter(|update| storable(update)) {
+        if is_tags(update) {
+            match &update.value {
+                Some(tags) => metadata.insert(String::from("tags"), normalize_tags(tags)),
+                None => metadata.remove("tags"),
+            };
+            continue;
+        }
+        let name = format!("{{{}}}{}", update.namespace, update.name);
+        match &update.value {
+            Some(value) => props.insert(name, value.clone()),
+            None => props.remove(&name),
+        };
+    }
+    if props.is_empty() {
+        metadata.remove(DEAD_PROPS_KEY);
+    } else {
+        metadata.insert(String::from(DEAD_PROPS_KEY), serde_json::to_string(&props)?);
+    }
+    let fits = metadata
+        .iter()
+        .map(|(k, v)| k.len() + v.len())
+        .sum::<usize>()
+        <= MAX_METADATA_LEN;
+    let applied = fits && updates.iter().all(storable);
+    if applied && !updates.is_empty() {
+        copy_object_with(&bucket, key, key, |kept| *kept = metadata).await?;
+    }
+
+    let status = |update: &xml::Update| match (storable(update), applied) {
+        (false, _) => "403 Forbidden",
+        (true, true) => "200 OK",
+        (true, false) if !fits => "507 Insufficient Storage",
+        (true, false) => "424 Failed Dependency",
+    };
+    let mut propstats = String::new();
+    for group in [
+        "200 OK",
+        "403 Forbidden",
+        "424 Failed Dependency",
+        "507 Insufficient Storage",
+    ] {
+        // Each property is echoed in its own namespace.
+        let echoed = updates
+            .iter()
+            .filter(|update| status(update) == group)
+            .map(|update| {
+                format!(
+                    "<{} xmlns=\"{}\"/>",
+                    update.name,
+                    xml::escape(&update.namespace)
+                )
+            })
+            .collect::<String>();
+        if !echoed.is_empty() {
+            propstats.push_str(&format!(
+                "<propstat><prop>{}</prop><status>HTTP/1.1 {}</status></propstat>",
+                echoed, group
+            ));
+        }
+    }
+    multistatus(
+        format!(
+            "<response><href>{}</href>{}</response>",
+            xml::escape(&path::href(key)),
+            propstats
+        ),
+        &config,
+    )
+}
+
+/// `X-Object-Tags: a=1, b=2` is stored as `a=1,b=2`.
+fn normalize_tags(tags: &str) -> String {
+    tags.split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+async fn handle_mkcol(req: Request, bucket: Bucket, _config: Config) -> Result<Response> {
+    let url = req.url()?;
+    let key = &path::key_of(url.path());
+    if key.is_empty() {
+        return Response::error("Method Not Found", 405);
+    }
+    if let Some(locked) = check_lock(&req, &bucket, key, false).await? {
+        return Ok(locked);
+    }
+    // flag: The folder has been created for R2.
+    let flag = key.to_string() + "/";
+    // `If-None-Match: *` asks to create only if absent, so a failed
+    // precondition is reported rather than a conflict.
+    let exists = if req.headers().get("If-None-Match")?.as_deref() == Some("*") {
+        Response::error("Precondition Failed", 412)
+    } else {
+        Response::error("Conflict", 409)
+    };
+    if retry(|| bucket.head(&flag)).await?.is_some() {
+        return exists;
+    }
+    // Two clients can both get past the check above; the create-only write
+    // lets exactly one of them make the marker.
+    let created = bucket
+        .put(&flag, Data::from(String::from("")))
+        .only_if(Conditional {
+            etag_does_not_match: Some(String::from("*")),
+            ..Default::default()
+        })
+        .execute()
+        .await;
+    if let Err(e) = created {
+        if retry(|| bucket.head(&flag)).await?.is_some() {
+            return exists;
+        }
+        return Err(e);
+    }
+    empty_response(201)
+}
+
+/// Hidden keys are left out of listings and can't be listed themselves. The
+/// trash and the versions are hidden from every other listing, but list what
+/// they keep when asked for explicitly, as `.trash/` or `.versions/`.
+async fn handle_propfind(mut req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    let url = req.url()?;
+    let key = &path::key_of(url.path());
+    if config.is_hidden(key) && !config.is_kept(key) {
+        return Response::error("Not Found", 404);
+    }
+    if config.is_write_only(key) {
+        return write_only_response();
+    }
+    // Every request is answered as `allprop`, which is what an empty body
+    // means; a body that isn't XML at all is refused.
+    let body = req.text().await?;
+    if !body.trim().is_empty() && !xml::is_well_formed(&body) {
+        return Response::error("Bad Request: PROPFIND body is not well-formed XML", 400);
+    }
+    let mut page = multistatus_start(&config);
+
+    let mut headers = Headers::new();
+    headers.append("Content-Type", XML_CONTENT_TYPE)?;
+    headers.append("Accept-Ranges", "none")?;
+    // R2 Storage lacks a traditional directory structure.
+    if !url.path().ends_with('/') && !key.is_empty() {
+        match retry(|| bucket.head(key)).await? {
+            Some(object) => {
+                let href = path::href(key);
+                let object = bound(&bucket, object, &config).await?;
+                let lock = lock::load(&bucket, key).await?;
+                page.push_str(
+                    &DavBuilder::new()
+                        .object(&href, Some(&object))
+                        .lock(lock.as_ref())
+                        .build(),
+                );
+                // `?versions` adds the retained versions, each of which GET
+                // reads and a COPY back over the file restores.
+                if url.query_pairs().any(|(k, _)| k == "versions") {
+                    let prefix = format!("{}/{}/", VERSIONS_PREFIX, key);
+                    let versions = list_all_files(&bucket, prefix.as_str(), &config).await?;
+                    if versions.truncated {
+                        return Response::error(
+                            "Insufficient Storage: file keeps too many versions",
+                            507,
+                        );
+                    }
+                    for version in versions.objects {
+                        let href = path::href(&version.key());
+                        page.push_str(&DavBuilder::new().object(&href, Some(&version)).build());
+                    }
+                }
+                page.push_str("</multistatus>");
+                headers.append("ETag", &object.http_etag())?;
+                return Ok(Response::ok(page)?.with_headers(headers));
+            }
+            None => return Response::error("Not Found", 404),
+        }
+    }
+    // `file.txt/` names a collection that doesn't exist, not the file.
+    if !key.is_empty() && retry(|| bucket.head(key)).await?.is_some() {
+        return Response::error("Not Found: not a collection", 404);
+    }
+
+    // `Depth: 1,noroot` and `Prefer: depth-noroot` (RFC 8144) both leave the
+    // collection itself out and list only its members.
+    let depth = req.headers().get("Depth")?.unwrap_or(String::from("1"));
+    let (depth, noroot) = match depth.split_once(',') {
+        Some((depth, flag)) if flag.trim().eq_ignore_ascii_case("noroot") => {
+            (depth.trim().to_string(), true)
+        }
+        _ => (depth, false),
+    };
+    let prefer_noroot = req.headers().get("Prefer")?.is_some_and(|prefer| {
+        prefer
+            .split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case("depth-noroot"))
+    });
+    if prefer_noroot {
+        headers.append("Preference-Applied", "depth-noroot")?;
+    }
+    let mut xml = String::new();
+    if !noroot && !prefer_noroot {
+        let href = path::href(key);
+        let lock = lock::load(&bucket, key).await?;
+        let usage = if key.is_empty() && config.root_usage {
+            Some(root_usage(&bucket, &url, &config).await?)
+        } else {
+            None
+        };
+        xml = DavBuilder::new()
+            .object(&href, None)
+            .lock(lock.as_ref())
+            .usage(usage)
+            .build();
+    }
+
+    match depth.as_str() {
+        "0" => {
+            xml.push_str("</multistatus>");
+            page.push_str(&xml);
+            Ok(Response::ok(page)?.with_headers(headers))
         }
-        _ => {
-            let mut headers = Headers::new();
-            headers.append("WWW-Authenticate", "Basic realm=\"webdav\"")?;
-            Ok(Response::error("Unauthorized", 401)?.with_headers(headers))
+        "1" => {
+            let prefix = collection_prefix(key);
+            let paging = page_params(&url);
+            let is_first_page = !matches!(paging, Some((_, Some(_))));
+            let (objects, folders) = match paging {
+                Some((limit, cursor)) => {
+                    let (objects, folders, next_cursor) =
+                        list_level_page(&bucket, &prefix, limit, cursor).await?;
+                    if let Some(next_cursor) = next_cursor {
+                        headers.append("X-Next-Cursor", &next_cursor)?;
+                    }
+                    (objects, folders)
+                }
+                None => list_level(&bucket, &prefix, config.list_page_size).await?,
+            };
+            // An empty collection still lists its marker, so nothing at all
+            // below the prefix means there is no such collection. The root
+            // always exists.
+            if objects.is_empty() && folders.is_empty() && is_first_page && !key.is_empty() {
+                return Response::error("Not Found", 404);
+            }
+            let etag = listing_etag(&objects, &folders);
+            headers.append("ETag", &etag)?;
+            if req
+                .headers()
+                .get("If-None-Match")?
+                .is_some_and(|tags| etag::weak_match(&tags, &etag))
+            {
+                return Ok(Response::empty()?.with_status(304).with_headers(headers));
+            }
+            let locks = lock::list_under(&bucket, &prefix).await?;
+            for object in objects {
+                let full_key = object.key();
+                let o_key = &full_key[prefix.len()..];
+                // The collection's own marker is the collection, not a child.
+                if o_key.is_empty() || !config.is_listed_in(key, &full_key) {
+                    continue;
+                }
+                let href = path::href(&full_key);
+                let object = bound(&bucket, object, &config).await?;
+                let builder = DavBuilder::new().object(&href, Some(&object));
+                xml.push_str(&builder.lock(locks.get(&full_key)).build());
+            }
+            for folder in folders {
+                if !config.is_listed_in(key, &folder) {
+                    continue;
+                }
+                let href = format!("{}/", path::href(&folder));
+                let builder = DavBuilder::new().object(&href, None);
+                xml.push_str(&builder.lock(locks.get(&folder)).build());
+            }
+            xml.push_str("</multistatus>");
+            page.push_str(&xml);
+
+            Ok(Response::ok(page)?.with_headers(headers))
         }
+        "infinity" => {
+            let started = Date::now().as_millis();
+            let prefix = collection_prefix(key);
+            let deadline = started + config.propfind_time_budget_ms;
+            // A whole-bucket listing can outgrow what a single Worker invocation
+            // is able to render, so listing stops at the first object past the
+            // entry cap or the time budget, and the request is refused rather
+            // than killed mid-way.
+            let listing = list_files_up_to(
+                &bucket,
+                prefix.as_str(),
+                config.list_page_size,
+                config.max_propfind_entries.min(config.max_listed_objects),
+                Some(deadline),
+            )
+            .await?;
+            if listing.truncated {
+                return Response::error("Insufficient Storage", 507);
+            }
+            let objects = listing.objects;
+            if objects.is_empty() && !key.is_empty() {
+                return Response::error("Not Found", 404);
+            }
+            let locks = lock::list_under(&bucket, &prefix).await?;
+            let mut folders = HashSet::new();
+            for object in objects {
+                // Bindings are resolved one by one, so rendering takes time too.
+                if Date::now().as_millis() > deadline {
+                    return Response::error("Insufficient Storage", 507);
+                }
+                let o_key = object.key();
+                let name = &o_key[prefix.len()..];
+                if !config.is_listed_in(key, &o_key) {
+                    continue;
+                }
+                // every ancestor below the requested collection is listed once
+                for (i, _) in name.match_indices('/') {
+                    let folder = format!("{}{}", prefix, &name[..i]);
+                    if folders.insert(folder.clone()) {
+                        let href = format!("{}/", path::href(&folder));
+                        let builder = DavBuilder::new().object(&href, None);
+                        xml.push_str(&builder.lock(locks.get(&folder)).build());
+                    }
+                }
+                if !name.is_empty() && !name.ends_with('/') {
+                    let href = path::href(&o_key);
+                    let object = bound(&bucket, object, &config).await?;
+                    let builder = DavBuilder::new().object(&href, Some(&object));
+                    xml.push_str(&builder.lock(locks.get(&o_key)).build());
+                }
+            }
+            xml.push_str("</multistatus>");
+            page.push_str(&xml);
+            Ok(Response::ok(page)?.with_headers(headers))
+        }
+        _ => Response::error("Forbidden", 403),
+    }
+}
+
+/// Weak validator for a listing, so sync clients polling an unchanged
+/// collection get a `304`. It covers the name and etag of every listed object
+/// and the name of every sub-collection, hashed with FNV-1a; anything added,
+/// removed or rewritten at this level changes it.
+fn listing_etag(objects: &[Object], folders: &[String]) -> String {
+    weak_etag(
+        objects
+            .iter()
+            .flat_map(|object| [object.key(), object.etag()])
+            .chain(folders.iter().cloned()),
+    )
+}
+
+/// Hashes `parts` each followed by a NUL, so moving a boundary between two
+/// parts changes the hash too.
+fn weak_etag(parts: impl IntoIterator<Item = String>) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in part.bytes().chain(std::iter::once(0)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("W/\"{:016x}\"", hash)
+}
+
+/// `Expect: 100-continue` is answered by Cloudflare's edge before the Worker
+/// runs, so by the time the body is read here the client is already sending
+/// it. Any other expectation cannot be met.
+async fn handle_put(mut req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    let url = req.url()?;
+    let key = &path::key_of(url.path());
+    if key.is_empty() {
+        return Response::error("Method Not Found", 405);
+    }
+    // A trailing slash names a collection, which PUT can't create, except for
+    // clients that are known to mean MKCOL by it.
+    if url.path().ends_with('/') {
+        if config.put_slash_as_mkcol {
+            return handle_mkcol(req, bucket, config).await;
+        }
+        return Response::error("Method Not Allowed", 405);
+    }
+    if let Some(expect) = req.headers().get("Expect")? {
+        if !expect.eq_ignore_ascii_case("100-continue") {
+            return Response::error("Expectation Failed", 417);
+        }
+    }
+    let content_type = req.headers().get("Content-Type")?;
+    if let Some((text, status)) =
+        write_refusal(&bucket, key, content_type.as_deref(), &config).await?
+    {
+        return Response::error(text, status);
+    }
+    if let Some(locked) = check_lock(&req, &bucket, key, false).await? {
+        return Ok(locked);
+    }
+    // `If-Match` is resolved to the one etag it accepts right now, and R2 then
+    // refuses the write if the object changed in between.
+    let expected = match req.headers().get("If-Match")? {
+        Some(if_match) => match retry(|| bucket.head(key)).await? {
+            Some(object) if etag::strong_match(&if_match, &object.http_etag()) => {
+                Some(object.etag())
+            }
+            _ => return Response::error("Precondition Failed", 412),
+        },
+        None => None,
+    };
+    // `If-None-Match` fails on a file whose etag it names, or on any file
+    // for `*`. R2 is asked to enforce `*` too, so a concurrent create loses.
+    let if_none_match = req.headers().get("If-None-Match")?;
+    let create_only = if_none_match.as_deref().map(str::trim) == Some("*");
+    if let Some(if_none_match) = &if_none_match {
+        if let Some(object) = retry(|| bucket.head(key)).await? {
+            if etag::weak_match(if_none_match, &object.http_etag()) {
+                return Response::error("Precondition Failed", 412);
+            }
+        }
+    }
+    before_overwrite(&bucket, &url, key, &config).await?;
+    let mut custom_metadata = HashMap::new();
+    if let Some(tags) = req.headers().get("X-Object-Tags")? {
+        custom_metadata.insert(String::from("tags"), normalize_tags(&tags));
+    }
+    // `X-Amz-Meta-*` as S3 tools send it, under names that can double as
+    // XML element names in PROPFIND.
+    for (name, value) in req.headers().entries() {
+        let name = name.to_lowercase();
+        let valid = name.strip_prefix(AMZ_META_PREFIX).is_some_and(|n| {
+            !n.is_empty()
+                && n.bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        });
+        if valid {
+            custom_metadata.insert(name, value);
+        }
+    }
+    // Kept so the file downloads the way it was uploaded, e.g. as an
+    // attachment under a given name, and reported in PROPFIND.
+    let http_metadata = HttpMetadata {
+        content_disposition: req.headers().get("Content-Disposition")?,
+        ..Default::default()
+    };
+    let data = req.bytes().await?;
+    let mut put = bucket
+        .put(key, Data::from(data))
+        .http_metadata(http_metadata)
+        .custom_metadata(custom_metadata);
+    if expected.is_some() || create_only {
+        put = put.only_if(Conditional {
+            etag_matches: expected.clone(),
+            etag_does_not_match: create_only.then(|| String::from("*")),
+            ..Default::default()
+        });
+    }
+    if let Err(e) = put.execute().await {
+        // A refused conditional write surfaces as an error; tell it apart
+        // from a storage failure by whether the object moved on.
+        let current = retry(|| bucket.head(key)).await?.map(|o| o.etag());
+        if (expected.is_some() && current != expected) || (create_only && current.is_some()) {
+            return Response::error("Precondition Failed", 412);
+        }
+        return Err(e);
+    }
+    empty_response(201)
+}
+
+/// Why no file may be written at `key`, as the reason and status PUT answers
+/// with. The S3 import holds every key it brings in to the same rules.
+async fn write_refusal(
+    bucket: &Bucket,
+    key: &str,
+    content_type: Option<&str>,
+    config: &Config,
+) -> Result<Option<(&'static str, u16)>> {
+    if config.filter_apple_double && is_apple_double(key) {
+        return Ok(Some(("Forbidden", 403)));
+    }
+    if !config.accepts_upload(key, content_type) {
+        return Ok(Some(("Unsupported Media Type", 415)));
+    }
+    // Writing `foo` next to a `foo/` collection would shadow it.
+    if is_collection(bucket, key).await? {
+        return Ok(Some(("Conflict", 409)));
+    }
+    // Nor can a file go below another file: `a/b.txt` needs `a` to be a
+    // collection, or not exist yet.
+    let parent = key.rsplit_once('/').map_or("", |(parent, _)| parent);
+    if !parent.is_empty() && retry(|| bucket.head(parent)).await?.is_some() {
+        return Ok(Some(("Conflict: the parent is a file", 409)));
+    }
+    Ok(None)
+}
+
+/// Lets go of what a write to `key` replaces: the cached copy is dropped
+/// and, with `VERSIONING`, the current body is kept as a version.
+async fn before_overwrite(bucket: &Bucket, url: &Url, key: &str, config: &Config) -> Result<()> {
+    if config.edge_cache {
+        purge_cached(bucket, url, key).await?;
+    }
+    if config.versioning && retry(|| bucket.head(key)).await?.is_some() {
+        let version = format!("{}/{}/{}", VERSIONS_PREFIX, key, Date::now().as_millis());
+        copy_object(bucket, key, &version).await?;
     }
+    Ok(())
 }
 
-async fn list_all_files(bucket: &Bucket, prefix: impl Into<String> + Copy) -> Result<Vec<Object>> {
-    let mut files = vec![];
-    let mut cursor = None;
-    loop {
-        let mut list_req = bucket
-            .list()
-            .include(vec![Include::HttpMetadata, Include::CustomMetadata]);
-        if !prefix.into().is_empty() {
-            list_req = list_req.prefix(prefix);
+/// [VERSION-CONTROL method](https://www.rfc-editor.org/rfc/rfc3253#section-3.5)
+///
+/// With `VERSIONING` every file is already under version control, so putting
+/// one under it is a no-op that only checks the file exists.
+async fn handle_version_control(req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    let url = req.url()?;
+    let key = &path::key_of(url.path());
+    if config.is_hidden(key) || retry(|| bucket.head(key)).await?.is_none() {
+        return Response::error("Not Found", 404);
+    }
+    empty_response(200)
+}
+
+/// [Add-Member POST](https://www.rfc-editor.org/rfc/rfc5995)
+///
+/// POSTing to a collection stores the body under a name chosen by the server,
+/// derived from the current time, and reports it in `Location`.
+async fn handle_post(mut req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    let url = req.url()?;
+    let key = &path::key_of(url.path());
+    if key == BULK_DELETE_KEY {
+        return handle_bulk_delete(req, bucket, config).await;
+    }
+    if key == IMPORT_KEY {
+        return handle_import(req, bucket, config).await;
+    }
+    if config.is_hidden(key) {
+        return Response::error("Not Found", 404);
+    }
+    if !key.is_empty() && !is_collection(&bucket, key).await? {
+        return Response::error("Method Not Allowed", 405);
+    }
+    if let Some(locked) = check_lock(&req, &bucket, key, false).await? {
+        return Ok(locked);
+    }
+    let content_type = req.headers().get("Content-Type")?;
+    let prefix = collection_prefix(key);
+    let stamp = Date::now().as_millis();
+    let mut member = format!("{}{}", prefix, stamp);
+    let mut n = 1;
+    while retry(|| bucket.head(&member)).await?.is_some() || is_collection(&bucket, &member).await?
+    {
+        member = format!("{}{}-{}", prefix, stamp, n);
+        n += 1;
+    }
+    // The server picks the name, but the upload filters still apply to it.
+    if !config.accepts_upload(&member, content_type.as_deref()) {
+        return Response::error("Unsupported Media Type", 415);
+    }
+    let data = req.bytes().await?;
+    bucket.put(&member, Data::from(data)).execute().await?;
+
+    let location = url.join(&path::href(&member)).map_err(|_| "Invalid URL")?;
+    let mut headers = Headers::new();
+    headers.append("Location", location.as_str())?;
+    headers.append("Content-Length", "0")?;
+    Ok(Response::empty()?.with_status(201).with_headers(headers))
+}
+
+/// Refuses a bucket-wide operation to anyone signed in with other than the
+/// `ADMIN_USERNAME` credentials.
+fn check_admin(req: &Request, config: &Config) -> Result<Option<Response>> {
+    let credentials = req
+        .headers()
+        .get("Authorization")?
+        .and_then(|auth| basic_credentials(&auth));
+    if config.is_admin(credentials.as_ref()) {
+        return Ok(None);
+    }
+    Response::error("Forbidden: admin credentials required", 403).map(Some)
+}
+
+/// `POST /.dav/bulk-delete` with a JSON array of file keys deletes them all,
+/// answering with the status of each: `204` deleted, `404` missing, `403`
+/// hidden, `423` locked without its token in `If`. Without soft delete the
+/// keys go in batched R2 deletes. Only the admin may run it.
+async fn handle_bulk_delete(mut req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    if let Some(refused) = check_admin(&req, &config)? {
+        return Ok(refused);
+    }
+    let keys = match serde_json::from_str::<Vec<String>>(&req.text().await?) {
+        Ok(keys) => keys,
+        Err(_) => return Response::error("Bad Request: expected a JSON array of keys", 400),
+    };
+    let trash = config
+        .soft_delete
+        .then(|| format!("{}/{}/", TRASH_PREFIX, Date::now().as_millis()));
+    let url = req.url()?;
+
+    let mut results = vec![];
+    let mut batch = vec![];
+    let mut deleted = vec![];
+    for key in keys {
+        let key = normalize_key(&key);
+        let status = if key.is_empty() || config.is_hidden(&key) {
+            403
+        } else if retry(|| bucket.head(&key)).await?.is_none() {
+            404
+        } else if check_lock(&req, &bucket, &key, false).await?.is_some() {
+            423
+        } else {
+            if config.edge_cache {
+                purge_cached(&bucket, &url, &key).await?;
+            }
+            match &trash {
+                Some(trash) => remove_object(&bucket, &key, Some(trash)).await?,
+                None => batch.push(key.clone()),
+            }
+            deleted.push(key.clone());
+            204
+        };
+        results.push(serde_json::json!({ "key": key, "status": status }));
+    }
+    for chunk in batch.chunks(1000) {
+        bucket.delete_multiple(chunk.to_vec()).await?;
+    }
+    // Deleted files take their locks with them, as for DELETE.
+    for key in &deleted {
+        lock::remove_all(&bucket, key).await?;
+    }
+
+    let mut headers = Headers::new();
+    headers.append("Content-Type", "application/json")?;
+    Ok(Response::ok(serde_json::Value::from(results).to_string())?.with_headers(headers))
+}
+
+/// `POST /.dav/import` copies one page of objects from an S3 bucket, given as
+///
+/// ```json
+/// { "endpoint": "https://s3.us-east-1.amazonaws.com", "region": "us-east-1",
+///   "bucket": "old", "access_key_id": "...", "secret_access_key": "...",
+///   "prefix": "", "limit": 100, "cursor": null }
+/// ```
+///
+/// and answers with what it imported and the `cursor` to POST again with
+/// until it comes back `null`. Bodies are streamed from S3 into R2, and each
+/// key is held to the rules of a PUT: locks, upload filters, versioning.
+/// Keys that fail are listed with a status under `failed`. Only available
+/// with `ENABLE_S3_IMPORT` and to the admin; the credentials are used, never
+/// kept.
+async fn handle_import(mut req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    if !config.s3_import {
+        return Response::error("Forbidden", 403);
+    }
+    if let Some(refused) = check_admin(&req, &config)? {
+        return Ok(refused);
+    }
+    let body: serde_json::Value = match serde_json::from_str(&req.text().await?) {
+        Ok(body) => body,
+        Err(_) => return Response::error("Bad Request: expected a JSON object", 400),
+    };
+    let field = |name: &str| body.get(name).and_then(|v| v.as_str()).map(String::from);
+    let source = match (
+        field("endpoint"),
+        field("region"),
+        field("bucket"),
+        field("access_key_id"),
+        field("secret_access_key"),
+    ) {
+        (Some(endpoint), Some(region), Some(s3_bucket), Some(access_key_id), Some(secret)) => {
+            s3::Source {
+                endpoint,
+                region,
+                bucket: s3_bucket,
+                access_key_id,
+                secret_access_key: secret,
+            }
         }
-        if let Some(c) = cursor {
-            list_req = list_req.cursor(c);
+        _ => return Response::error("Bad Request: missing S3 source fields", 400),
+    };
+    let limit = body
+        .get("limit")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(100)
+        .clamp(1, 1000) as u32;
+    let prefix = field("prefix").unwrap_or_default();
+    let page = source
+        .list(&prefix, field("cursor").as_deref(), limit)
+        .await?;
+
+    let url = req.url()?;
+    let mut imported = vec![];
+    let mut failed = vec![];
+    for s3_key in page.keys {
+        // S3 folder placeholders become collection markers.
+        let key = match normalize_key(&s3_key) {
+            key if s3_key.ends_with('/') && !key.is_empty() => format!("{}/", key),
+            key => key,
+        };
+        if key.is_empty() || path::is_too_long(&key) || config.is_hidden(&key) {
+            failed.push(serde_json::json!({ "key": s3_key, "status": 400 }));
+            continue;
         }
-        let objects = list_req.execute().await?;
-        files.extend(objects.objects());
-        if !objects.truncated() {
-            break;
+        if check_lock(&req, &bucket, &key, false).await?.is_some() {
+            failed.push(serde_json::json!({ "key": s3_key, "status": 423 }));
+            continue;
+        }
+        // A key that fails is reported and the rest of the page carries on,
+        // so what was imported before it isn't lost from the report.
+        let mut res = match retry(|| source.get(&s3_key)).await {
+            Ok(res) => res,
+            Err(e) => {
+                console_error!("[ERROR] {}: {}", s3_key, e);
+                failed.push(serde_json::json!({ "key": s3_key, "status": 502 }));
+                continue;
+            }
+        };
+        let size = res
+            .headers()
+            .get("Content-Length")?
+            .and_then(|v| v.parse::<u64>().ok());
+        let size = match (res.status_code(), size) {
+            (200, Some(size)) => size,
+            (status, _) => {
+                failed.push(serde_json::json!({ "key": s3_key, "status": status }));
+                continue;
+            }
+        };
+        let content_type = res.headers().get("Content-Type")?;
+        if !key.ends_with('/') {
+            if let Some((_, status)) =
+                write_refusal(&bucket, &key, content_type.as_deref(), &config).await?
+            {
+                failed.push(serde_json::json!({ "key": s3_key, "status": status }));
+                continue;
+            }
+        }
+        let written = async {
+            before_overwrite(&bucket, &url, &key, &config).await?;
+            let http_metadata = HttpMetadata {
+                content_type,
+                ..Default::default()
+            };
+            bucket
+                .put(
+                    &key,
+                    Data::Stream(FixedLengthStream::wrap(res.stream()?, size)),
+                )
+                .http_metadata(http_metadata)
+                .execute()
+                .await
+        };
+        match written.await {
+            Ok(_) => imported.push(key),
+            Err(e) => {
+                console_error!("[ERROR] {}: {}", key, e);
+                failed.push(serde_json::json!({ "key": s3_key, "status": 500 }));
+            }
         }
-        cursor = objects.cursor();
     }
-    Ok(files)
-}
 
-/// [Not advertised in OPTIONS response](http://www.webdav.org/specs/rfc4918.html#HEADER_DAV)
-async fn handle_options(_req: Request, _bucket: Bucket) -> Result<Response> {
     let mut headers = Headers::new();
-    headers.append("DAV", "1, 2")?;
-    headers.append("Allow", METHODS.join(", ").as_str())?;
-    Ok(Response::empty()?.with_status(204).with_headers(headers))
+    headers.append("Content-Type", "application/json")?;
+    let report = serde_json::json!({
+        "imported": imported,
+        "failed": failed,
+        "cursor": page.cursor,
+    });
+    Ok(Response::ok(report.to_string())?.with_headers(headers))
 }
 
-/// [HEAD method](http://www.webdav.org/specs/rfc4918.html#n-get--head-for-collections)
-async fn handle_head(req: Request, bucket: Bucket) -> Result<Response> {
-    let res = handle_get(req, bucket).await?;
-    Ok(Response::empty()?
-        .with_status(res.status_code())
-        .with_headers(res.headers().clone()))
+async fn handle_copy(req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    handle_transfer(req, bucket, config, false).await
+}
+
+/// [MOVE method](http://www.webdav.org/specs/rfc4918.html#METHOD_MOVE)
+///
+/// R2 offers neither rename nor server-side copy, so a move is a streamed
+/// copy of each object followed by deleting the source.
+async fn handle_move(req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    handle_transfer(req, bucket, config, true).await
 }
 
-async fn handle_get(req: Request, bucket: Bucket) -> Result<Response> {
+async fn handle_transfer(
+    req: Request,
+    bucket: Bucket,
+    config: Config,
+    remove_source: bool,
+) -> Result<Response> {
     let url = req.url()?;
-    let key = url.path().trim_matches('/');
-    if url.path().ends_with('/') {
-        let page = r#"<!DOCTYPE HTML PUBLIC "-//IETF//DTD HTML 2.0//EN"><html><head><title>404 Not Found</title></head><body><h1>Not Found</h1><p>The requested URL was not found on this server.</p></body></html>"#;
-        let mut headers = Headers::new();
-        headers.append("Content-Type", "text/html")?;
-        return Ok(Response::ok(page)?.with_headers(headers));
+    let key = &stored_key(&bucket, &url).await?;
+    let (destination, into_collection) = match parse_destination(&req)? {
+        Some(destination) => destination,
+        None => return Response::error("Bad Request", 400),
+    };
+    if key.is_empty() {
+        return Response::error("Forbidden", 403);
+    }
+    // Copying out of an upload-only folder would read its contents back.
+    if config.is_write_only(key) {
+        return Response::error("Forbidden", 403);
+    }
+    let source = retry(|| bucket.head(key)).await?;
+    let is_file = source.is_some();
+    // A file sent to `folder/` lands inside it under its own name.
+    let destination = if is_file && into_collection {
+        let name = key.rsplit('/').next().unwrap_or(key);
+        format!("{}{}", collection_prefix(&destination), name)
+    } else {
+        destination
+    };
+    if destination.is_empty() || destination == *key || config.is_hidden(&destination) {
+        return Response::error("Forbidden", 403);
+    }
+    // Nor into itself or onto one of its ancestors: filling `a/b` from `a`
+    // would eat into the very members being copied, and clearing `a` for
+    // `a/b.txt` would delete the source before it is copied.
+    if overlaps(key, &destination) {
+        return Response::error("Forbidden: source and destination overlap", 403);
     }
+    if path::is_too_long(&destination) {
+        return Response::error("URI Too Long: keys are limited to 1023 bytes", 414);
+    }
+    // A rename is an upload under the new name, so `x.txt` can't become a
+    // blocked `x.php`. Members of a collection keep their names.
+    if let Some(source) = &source {
+        let content_type = source.http_metadata().content_type;
+        if !config.accepts_upload(&destination, content_type.as_deref()) {
+            return Response::error("Unsupported Media Type", 415);
+        }
+    }
+    // MOVE takes the source away, so its locks matter as much as those on
+    // whatever the destination replaces.
+    if remove_source {
+        if let Some(locked) = check_lock(&req, &bucket, key, true).await? {
+            return Ok(locked);
+        }
+    }
+    if let Some(locked) = check_lock(&req, &bucket, &destination, true).await? {
+        return Ok(locked);
+    }
+    let overwrite = !req
+        .headers()
+        .get("Overwrite")?
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("F"));
 
-    if req.headers().get("Range")?.is_none() {
-        let object = bucket.get(key).execute().await?.ok_or("Object is None")?;
-        let http_meta_data = object.http_metadata();
-        let stream = object.body().ok_or("Body is None")?.stream()?;
-        return Ok(Response::from_stream(stream)?.with_headers(get_headers(http_meta_data)?));
+    if is_file {
+        let status = match clear_destination(&bucket, &destination, overwrite, &config).await? {
+            Ok(status) => status,
+            Err(refused) => return Ok(refused),
+        };
+        copy_object(&bucket, key, &destination).await?;
+        if remove_source {
+            retry(|| bucket.delete(key)).await?;
+            lock::remove_all(&bucket, key).await?;
+        }
+        return empty_response(status);
+    }
+
+    // A collection COPY is either shallow or complete; MOVE is always
+    // complete. Checked before anything at the destination is cleared.
+    let shallow = match req.headers().get("Depth")?.as_deref() {
+        Some("0") if !remove_source => true,
+        None | Some("infinity") => false,
+        Some(_) => return Response::error("Bad Request", 400),
+    };
+    let prefix = format!("{}/", key);
+    let listing = list_all_files(&bucket, prefix.as_str(), &config).await?;
+    if listing.truncated {
+        return Response::error("Insufficient Storage: folder holds too many files", 507);
+    }
+    let files = listing.objects;
+    if files.is_empty() {
+        return Response::error("Not Found", 404);
+    }
+    let status = match clear_destination(&bucket, &destination, overwrite, &config).await? {
+        Ok(status) => status,
+        Err(refused) => return Ok(refused),
+    };
+    if shallow {
+        bucket
+            .put(format!("{}/", destination), Data::from(String::from("")))
+            .execute()
+            .await?;
+        return empty_response(status);
+    }
+    // The marker goes first and, for MOVE, comes away last, so each side
+    // keeps listing as a collection for as long as it holds members. A
+    // collection that only exists through its members still gets one at
+    // the destination.
+    let marker = files.iter().any(|f| f.key() == prefix);
+    let target_marker = format!("{}/", destination);
+    if marker {
+        copy_object(&bucket, &prefix, &target_marker).await?;
+    } else {
+        bucket
+            .put(&target_marker, Data::from(String::new()))
+            .execute()
+            .await?;
+    }
+    for f in files.iter().filter(|f| f.key() != prefix) {
+        let target = format!("{}/{}", destination, &f.key()[prefix.len()..]);
+        copy_object(&bucket, &f.key(), &target).await?;
+        if remove_source {
+            retry(|| bucket.delete(f.key())).await?;
+        }
+    }
+    if remove_source {
+        if marker {
+            retry(|| bucket.delete(&prefix)).await?;
+        }
+        lock::remove_all(&bucket, key).await?;
+    }
+    empty_response(status)
+}
+
+/// Whether either key lies below the other, so that a COPY or MOVE between
+/// them would clear or fill the objects it copies.
+fn overlaps(a: &str, b: &str) -> bool {
+    b.starts_with(&collection_prefix(a)) || a.starts_with(&collection_prefix(b))
+}
+
+/// The status of a COPY or MOVE onto `destination`: `201` when nothing is
+/// there yet, `204` when an existing file or collection was removed to make
+/// room. The refusal instead when `Overwrite: F` forbids replacing it, or
+/// when the collection there is too large to clear.
+async fn clear_destination(
+    bucket: &Bucket,
+    destination: &str,
+    overwrite: bool,
+    config: &Config,
+) -> Result<std::result::Result<u16, Response>> {
+    let file = retry(|| bucket.head(destination)).await?.is_some();
+    let members = list_all_files(bucket, format!("{}/", destination).as_str(), config).await?;
+    if !file && members.objects.is_empty() {
+        return Ok(Ok(201));
+    }
+    if !overwrite {
+        return Response::error("Precondition Failed", 412).map(Err);
     }
-    Response::error("Method Not allowed", 405)
+    if members.truncated {
+        return Response::error(
+            "Insufficient Storage: destination holds too many files",
+            507,
+        )
+        .map(Err);
+    }
+    if file {
+        retry(|| bucket.delete(destination)).await?;
+    }
+    for member in members.objects {
+        retry(|| bucket.delete(member.key())).await?;
+    }
+    Ok(Ok(204))
+}
+
+/// Pipes the source body straight into the new key so large objects are never
+/// buffered in the Worker; http and custom metadata are carried over. The
+/// source size makes it a fixed-length upload, which R2 requires of streams.
+/// The binding has no server-side copy, so the bytes pass through either way.
+async fn copy_object(bucket: &Bucket, source: &str, destination: &str) -> Result<()> {
+    copy_object_with(bucket, source, destination, |_| {}).await
+}
+
+/// Like `copy_object`, letting the caller edit the custom metadata on the way.
+/// R2 can't update metadata in place, so copying onto the same key is how it
+/// gets changed.
+async fn copy_object_with(
+    bucket: &Bucket,
+    source: &str,
+    destination: &str,
+    edit: impl FnOnce(&mut HashMap<String, String>),
+) -> Result<()> {
+    let object = bucket
+        .get(source)
+        .execute()
+        .await?
+        .ok_or("Object is None")?;
+    let mut custom_metadata = object.custom_metadata()?;
+    edit(&mut custom_metadata);
+    let stream = object.body().ok_or("Body is None")?.stream()?;
+    bucket
+        .put(
+            destination,
+            Data::Stream(FixedLengthStream::wrap(stream, object.size() as u64)),
+        )
+        .http_metadata(object.http_metadata())
+        .custom_metadata(custom_metadata)
+        .execute()
+        .await?;
+    Ok(())
 }
 
-async fn handle_delete(req: Request, bucket: Bucket) -> Result<Response> {
+/// The `Destination` header may be an absolute URL or an absolute path; only
+/// destinations on the same host are accepted. Alongside the key, reports
+/// whether the destination was written as a collection (trailing slash).
+fn parse_destination(req: &Request) -> Result<Option<(String, bool)>> {
+    let destination = match req.headers().get("Destination")? {
+        Some(destination) => destination,
+        None => return Ok(None),
+    };
     let url = req.url()?;
-    let key = url.path().trim_matches('/');
+    let target = url.join(&destination).map_err(|_| "Invalid Destination")?;
+    if target.host_str() != url.host_str() || !path::is_mounted(target.path()) {
+        return Ok(None);
+    }
+    Ok(Some((
+        path::key_of(target.path()),
+        target.path().ends_with('/'),
+    )))
+}
 
-    let source = bucket.head(key).await?;
-    if source.is_none() {
-        let files = list_all_files(&bucket, key).await?;
-        if files.is_empty() {
-            return Response::error("Not Found", 404);
-        }
-        for f in files {
-            bucket.delete(f.key()).await?;
+/// The file `object` stands for when it is a binding made by BIND: an empty
+/// object whose `bind` metadata names the target key.
+fn bind_target(object: &Object) -> Option<String> {
+    object.custom_metadata().ok()?.get("bind").cloned()
+}
+
+/// The object PROPFIND describes for `object`: the file a binding names, or
+/// `object` itself, also when that file has gone or can't be read.
+async fn bound(bucket: &Bucket, object: Object, config: &Config) -> Result<Object> {
+    match bind_target(&object) {
+        Some(target) if !config.is_hidden(&target) && !config.is_write_only(&target) => {
+            Ok(retry(|| bucket.head(&target)).await?.unwrap_or(object))
         }
+        _ => Ok(object),
     }
-    bucket.delete(key).await?;
-    Ok(Response::empty()?.with_status(204))
 }
 
-async fn handle_proppatch(req: Request, bucket: Bucket) -> Result<Response> {
-    todo!()
+/// The new binding of a BIND or UNBIND body: `segment` within the collection
+/// at `key`, plus the `href`, when given.
+fn binding_of(key: &str, body: &str) -> Option<(String, Option<String>)> {
+    let segment = xml::element_text(body, "segment")?;
+    let segment = normalize_key(segment.trim());
+    if segment.is_empty() || segment.contains('/') {
+        return None;
+    }
+    let binding = format!("{}{}", collection_prefix(key), segment);
+    Some((binding, xml::element_text(body, "href")))
 }
 
-async fn handle_mkcol(req: Request, bucket: Bucket) -> Result<Response> {
+/// [BIND method](https://www.rfc-editor.org/rfc/rfc5842#section-4)
+///
+/// Adds a second name for a file without copying it: `segment` in the
+/// collection becomes an empty object naming the file at `href`, and GET or
+/// PROPFIND of it serve that file. Only files can be bound, and a binding to
+/// a binding names the file behind it, so bindings never chain.
+async fn handle_bind(mut req: Request, bucket: Bucket, config: Config) -> Result<Response> {
     let url = req.url()?;
-    let key = url.path().trim_matches('/');
-    if key.is_empty() {
-        return Response::error("Method Not Found", 405);
+    let key = &path::key_of(url.path());
+    if config.is_hidden(key) {
+        return Response::error("Not Found", 404);
     }
-    // flag: The folder has been created for R2.
-    let flag = key.to_string() + "/";
-    let object = bucket.head(&flag).await?;
-    if object.is_some() {
+    if !key.is_empty() && !is_collection(&bucket, key).await? {
+        return Response::error("Conflict: BIND needs a collection", 409);
+    }
+    let body = req.text().await?;
+    let (binding, href) = match binding_of(key, &body) {
+        Some((binding, Some(href))) => (binding, href),
+        _ => return Response::error("Bad Request: expected a segment and an href", 400),
+    };
+    let target = match url.join(href.trim()) {
+        Ok(target) if target.host_str() == url.host_str() && path::is_mounted(target.path()) => {
+            path::key_of(target.path())
+        }
+        _ => return Response::error("Bad Request: href must name a file on this server", 400),
+    };
+    if path::is_too_long(&binding) {
+        return Response::error("URI Too Long: keys are limited to 1023 bytes", 414);
+    }
+    let target = match retry(|| bucket.head(&target)).await? {
+        Some(object) => bind_target(&object).unwrap_or(target),
+        None => return Response::error("Conflict: only existing files can be bound", 409),
+    };
+    // Checked on the file finally bound, so a binding can't read a hidden or
+    // upload-only file back out, not even through another binding.
+    if config.is_hidden(&binding) || config.is_hidden(&target) || config.is_write_only(&target) {
+        return Response::error("Forbidden", 403);
+    }
+    if target == binding {
+        return Response::error("Forbidden: a file can't be bound to itself", 403);
+    }
+    if is_collection(&bucket, &binding).await? {
         return Response::error("Conflict", 409);
     }
+    if let Some(locked) = check_lock(&req, &bucket, &binding, false).await? {
+        return Ok(locked);
+    }
+    let existing = retry(|| bucket.head(&binding)).await?.is_some();
+    let overwrite = !req
+        .headers()
+        .get("Overwrite")?
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("F"));
+    if existing && !overwrite {
+        return Response::error("Precondition Failed", 412);
+    }
     bucket
-        .put(flag, Data::from(String::from("")))
+        .put(&binding, Data::from(String::new()))
+        .custom_metadata(HashMap::from([(String::from("bind"), target)]))
         .execute()
         .await?;
-    Ok(Response::empty()?.with_status(201))
+    empty_response(if existing { 200 } else { 201 })
+}
+
+/// [UNBIND method](https://www.rfc-editor.org/rfc/rfc5842#section-5)
+///
+/// Removes a binding made by BIND and leaves the file it names alone. Every
+/// other member has no name but its own, so DELETE is the way to remove it.
+async fn handle_unbind(mut req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    let url = req.url()?;
+    let key = &path::key_of(url.path());
+    let body = req.text().await?;
+    let binding = match binding_of(key, &body) {
+        Some((binding, _)) => binding,
+        None => return Response::error("Bad Request: expected a segment", 400),
+    };
+    if config.is_hidden(&binding) {
+        return Response::error("Not Found", 404);
+    }
+    match retry(|| bucket.head(&binding)).await? {
+        Some(object) if bind_target(&object).is_some() => {}
+        Some(_) => return Response::error("Forbidden: not a binding", 403),
+        None => return Response::error("Not Found", 404),
+    }
+    if let Some(locked) = check_lock(&req, &bucket, &binding, false).await? {
+        return Ok(locked);
+    }
+    retry(|| bucket.delete(&binding)).await?;
+    lock::remove(&bucket, &binding).await?;
+    empty_response(200)
 }
 
-async fn handle_propfind(req: Request, bucket: Bucket) -> Result<Response> {
+/// [LOCK method](http://www.webdav.org/specs/rfc4918.html#METHOD_LOCK)
+///
+/// A body asks for a new lock; an empty body refreshes the lock whose token
+/// is submitted in `If`.
+async fn handle_lock(mut req: Request, bucket: Bucket, config: Config) -> Result<Response> {
     let url = req.url()?;
-    let key = url.path().trim_matches('/');
-    let mut page = r#"<?xml version="1.0" encoding="utf-8"?>
-<multistatus xmlns="DAV:">"#
-        .to_string();
+    let key = &path::key_of(url.path());
+    if config.is_hidden(key) {
+        return Response::error("Not Found", 404);
+    }
+    let timeout = lock::timeout_secs(req.headers().get("Timeout")?.as_deref());
+    let body = req.text().await?;
 
-    let mut headers = Headers::new();
-    headers.append("Content-Type", "text/xml")?;
-    // R2 Storage lacks a traditional directory structure.
-    if !url.path().ends_with('/') && !key.is_empty() {
-        match bucket.head(key).await? {
-            Some(object) => {
-                let href = format!("/{}", object.key());
-                page.push_str(&DavBuilder::new().object(&href, Some(&object)).build());
-                page.push_str("</multistatus>");
-                return Ok(Response::ok(page)?.with_headers(headers));
+    if body.trim().is_empty() {
+        let submitted = lock::submitted_tokens(&req.headers().get("If")?.unwrap_or_default());
+        return match lock::covering(&bucket, key).await? {
+            Some(mut lock) if submitted.contains(&lock.token) => {
+                lock.refresh(timeout);
+                lock::store(&bucket, &lock).await?;
+                lock_response(&lock, 200)
             }
-            None => return Response::error("Not Found", 404),
+            _ => Response::error("Precondition Failed", 412),
+        };
+    }
+
+    if !xml::is_well_formed(&body) {
+        return Response::error("Bad Request", 400);
+    }
+    // Depth defaults to infinity for LOCK.
+    let infinite = match req.headers().get("Depth")?.as_deref() {
+        None | Some("infinity") => true,
+        Some("0") => false,
+        Some(_) => return Response::error("Bad Request", 400),
+    };
+    // A lock may not overlap another: none may cover the key, and a
+    // collection-wide lock needs every member to be unlocked.
+    if lock::covering(&bucket, key).await?.is_some()
+        || (infinite
+            && !lock::list_under(&bucket, &collection_prefix(key))
+                .await?
+                .is_empty())
+    {
+        return Response::error("Locked", 423);
+    }
+    // Locking an unmapped URL creates an empty file there, as RFC 4918 has
+    // it in place of the lock-null resources of RFC 2518; it then lists and
+    // reads like any other file.
+    let exists = key.is_empty()
+        || retry(|| bucket.head(key)).await?.is_some()
+        || is_collection(&bucket, key).await?;
+    if !exists {
+        if url.path().ends_with('/') {
+            return Response::error("Not Found", 404);
+        }
+        let parent = key.rsplit_once('/').map_or("", |(parent, _)| parent);
+        if !parent.is_empty() && !is_collection(&bucket, parent).await? {
+            return Response::error("Conflict", 409);
         }
+        bucket.put(key, Data::from(String::new())).execute().await?;
     }
+    let lock = lock::Lock::new(
+        key,
+        !xml::has_element(&body, "shared"),
+        infinite,
+        xml::element_text(&body, "owner").unwrap_or_default(),
+        timeout,
+    );
+    lock::store(&bucket, &lock).await?;
+    lock_response(&lock, if exists { 200 } else { 201 })
+}
 
-    let href = format!("/{}", key);
-    let mut xml = DavBuilder::new().object(&href, None).build();
-    let depth = req.headers().get("Depth")?.unwrap_or(String::from("1"));
+fn lock_response(lock: &lock::Lock, status: u16) -> Result<Response> {
+    let mut headers = Headers::new();
+    headers.append("Content-Type", XML_CONTENT_TYPE)?;
+    headers.append("Accept-Ranges", "none")?;
+    headers.append("Lock-Token", &format!("<{}>", lock.token))?;
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<prop xmlns="DAV:"><lockdiscovery>{}</lockdiscovery></prop>"#,
+        lock.activelock()
+    );
+    Ok(Response::ok(body)?
+        .with_status(status)
+        .with_headers(headers))
+}
 
-    match depth.as_str() {
-        "0" => {
-            xml.push_str("</multistatus>");
-            page.push_str(&xml);
-            Ok(Response::ok(page)?.with_headers(headers))
+/// `423 Locked` unless the `If` header submits the token of the lock covering
+/// `key` and, with `members`, of every lock below it too. A write carrying
+/// `If: (<opaquelocktoken:...>)` for the right lock goes through.
+async fn check_lock(
+    req: &Request,
+    bucket: &Bucket,
+    key: &str,
+    members: bool,
+) -> Result<Option<Response>> {
+    let if_header = req.headers().get("If")?;
+    if let Some(header) = &if_header {
+        if !if_header_holds(req, bucket, header).await? {
+            return Ok(Some(Response::error("Precondition Failed", 412)?));
         }
-        "1" => {
-            let objects = list_all_files(&bucket, key).await?;
-            if objects.is_empty() {
-                return Response::error("Not Found", 404);
-            }
-            let mut keys = vec![key.to_string()];
-            for object in objects {
-                let mut o_key = &object.key()[key.len()..];
-                o_key = o_key.trim_start_matches('/');
-                if !o_key.contains('/') {
-                    let href = format!("/{}", object.key());
-                    xml.push_str(&DavBuilder::new().object(&href, Some(&object)).build());
-                    continue;
-                }
-                // handle sub directory
-                let folder_name = o_key.split('/').next().unwrap().to_string();
-                if !keys.contains(&folder_name) {
-                    keys.push(folder_name.clone());
-                    let href = format!("/{}", folder_name);
-                    xml.push_str(&DavBuilder::new().object(&href, None).build());
-                }
-            }
-            xml.push_str("</multistatus>");
-            page.push_str(&xml);
+    }
+    let submitted = lock::submitted_tokens(&if_header.unwrap_or_default());
+    let mut locks: Vec<lock::Lock> = lock::covering(bucket, key).await?.into_iter().collect();
+    if members {
+        locks.extend(
+            lock::list_under(bucket, &collection_prefix(key))
+                .await?
+                .into_values(),
+        );
+    }
+    let missing = match locks.iter().find(|lock| !submitted.contains(&lock.token)) {
+        Some(lock) => lock,
+        None => return Ok(None),
+    };
+    // Names the lock root whose token was missing, as the precondition requires.
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<error xmlns="DAV:"><lock-token-submitted><href>{}</href></lock-token-submitted></error>"#,
+        xml::escape(&path::href(&missing.root))
+    );
+    let mut headers = Headers::new();
+    headers.append("Content-Type", XML_CONTENT_TYPE)?;
+    Ok(Some(
+        Response::ok(body)?.with_status(423).with_headers(headers),
+    ))
+}
 
-            Ok(Response::ok(page)?.with_headers(headers))
+/// An `If` header holds when any one of its lists does. A list is checked
+/// against the resource it is tagged with, so a MOVE can state conditions on
+/// both source and destination; untagged lists apply to the request URL.
+async fn if_header_holds(req: &Request, bucket: &Bucket, header: &str) -> Result<bool> {
+    let lists = match if_header::parse(header) {
+        Some(lists) => lists,
+        None => return Ok(false),
+    };
+    let url = req.url()?;
+    for list in lists {
+        let key = match &list.resource {
+            Some(tag) => match url.join(tag) {
+                Ok(target)
+                    if target.host_str() == url.host_str() && path::is_mounted(target.path()) =>
+                {
+                    path::key_of(target.path())
+                }
+                _ => continue,
+            },
+            None => path::key_of(url.path()),
+        };
+        let etag = retry(|| bucket.head(&key)).await?.map(|o| o.http_etag());
+        let lock = lock::covering(bucket, &key).await?;
+        let holds = list.conditions.iter().all(|condition| {
+            let matched = match &condition.state {
+                if_header::State::Token(token) => lock.as_ref().is_some_and(|l| &l.token == token),
+                if_header::State::ETag(tag) => etag.as_deref() == Some(tag.as_str()),
+            };
+            matched != condition.not
+        });
+        if holds {
+            return Ok(true);
         }
-        "infinity" => Response::error("Not Implemented", 501),
-        _ => Response::error("Forbidden", 403),
     }
+    Ok(false)
 }
 
-async fn handle_put(mut req: Request, bucket: Bucket) -> Result<Response> {
+/// [UNLOCK method](http://www.webdav.org/specs/rfc4918.html#METHOD_UNLOCK)
+async fn handle_unlock(req: Request, bucket: Bucket, _config: Config) -> Result<Response> {
     let url = req.url()?;
-    let key = url.path().trim_matches('/');
-    if key.is_empty() {
-        return Response::error("Method Not Found", 405);
+    let key = &path::key_of(url.path());
+    let token = req.headers().get("Lock-Token")?.unwrap_or_default();
+    let token = token.trim().trim_start_matches('<').trim_end_matches('>');
+    // Any resource the lock covers may be used to release it.
+    match lock::covering(&bucket, key).await? {
+        Some(lock) if lock.token == token => {
+            lock::remove(&bucket, &lock.root).await?;
+            empty_response(204)
+        }
+        _ => Response::error("Conflict: no such lock on this resource", 409),
     }
-    let data = req.bytes().await?;
-    bucket.put(key, Data::from(data)).execute().await?;
-    Ok(Response::empty()?.with_status(201))
 }
 
-async fn handle_copy(req: Request, bucket: Bucket) -> Result<Response> {
-    todo!()
+/// [SEARCH method](https://www.rfc-editor.org/rfc/rfc5323#section-2)
+///
+/// Only `basicsearch` with a `like` or `contains` condition on the file name is
+/// understood; the scope defaults to the request URL.
+async fn handle_search(mut req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    let url = req.url()?;
+    let body = req.text().await?;
+    if !xml::has_element(&body, "basicsearch") {
+        return Response::error("Unprocessable Entity", 422);
+    }
+    let pattern = match xml::element_text(&body, "literal") {
+        Some(literal) if xml::has_element(&body, "contains") => format!("%{}%", literal),
+        Some(literal) if xml::has_element(&body, "like") => literal,
+        _ => return Response::error("Unprocessable Entity", 422),
+    };
+    let scope = match xml::element_text(&body, "href") {
+        Some(href) => url.join(&href).map_err(|_| "Invalid scope")?,
+        None => url,
+    };
+    let key = &path::key_of(scope.path());
+    if config.is_hidden(key) && !config.is_kept(key) {
+        return Response::error("Not Found", 404);
+    }
+    // An upload-only folder can't be searched any more than listed.
+    if config.is_write_only(key) {
+        return write_only_response();
+    }
+    let recursive = xml::element_text(&body, "depth").as_deref() != Some("1");
+
+    let prefix = collection_prefix(key);
+    let listing = list_files_up_to(
+        &bucket,
+        prefix.as_str(),
+        config.list_page_size,
+        config.max_listed_objects,
+        None,
+    )
+    .await?;
+    if listing.truncated {
+        return Response::error("Insufficient Storage: scope holds too many files", 507);
+    }
+    let mut xml = String::new();
+    for object in listing.objects {
+        let o_key = object.key();
+        let name = &o_key[prefix.len()..];
+        if name.is_empty() || name.ends_with('/') || !config.is_listed_in(key, &o_key) {
+            continue;
+        }
+        if !recursive && name.contains('/') {
+            continue;
+        }
+        if like_match(&pattern, name.rsplit('/').next().unwrap_or(name)) {
+            let href = path::href(&o_key);
+            xml.push_str(&DavBuilder::new().object(&href, Some(&object)).build());
+        }
+    }
+    multistatus(xml, &config)
 }
 
-async fn handle_move(req: Request, bucket: Bucket) -> Result<Response> {
-    todo!()
+/// SQL-style `LIKE` as used by DASL: `%` matches any run, `_` one character.
+/// Matched greedily, going back only to the last `%` on a mismatch, so a
+/// pattern of many `%` takes linear rather than exponential time per try.
+fn like_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let n: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut i, mut j) = (0, 0);
+    // the last `%` seen, and where in `name` its run currently ends
+    let mut backtrack = None;
+    while j < n.len() {
+        match p.get(i) {
+            Some('%') => {
+                backtrack = Some((i, j));
+                i += 1;
+            }
+            Some(&c) if c == '_' || c == n[j] => {
+                i += 1;
+                j += 1;
+            }
+            _ => match backtrack {
+                // Let the `%` take one more character and try again.
+                Some((star, end)) => {
+                    backtrack = Some((star, end + 1));
+                    i = star + 1;
+                    j = end + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    p[i..].iter().all(|&c| c == '%')
 }
 
-async fn handle_lock(req: Request, _bucket: Bucket) -> Result<Response> {
-    let depth = req.headers().get("Depth")?.unwrap_or(String::from("0"));
-    let timeout = req
-        .headers()
-        .get("Timeout")?
-        .unwrap_or(String::from("Infinite"));
-    // TODO: parser xml and lock token
-    // <D:locktoken>
-    //   <D:href>opaquelocktoken:{}</D:href>
-    // </D:locktoken>
-    Response::ok(format!(
+/// The XML declaration and `<multistatus>` start tag, which also binds
+/// `CUSTOM_PREFIX` to the configured namespace of the custom properties.
+fn multistatus_start(config: &Config) -> String {
+    format!(
         r#"<?xml version="1.0" encoding="utf-8"?>
-<D:prop xmlns:D="DAV:">
-  <D:lockdiscovery>
-    <D:activelock>
-      <D:locktype><D:write/></D:locktype>
-      <D:lockscope><D:exclusive/></D:lockscope>
-      <D:depth>{}</D:depth>
-      <ns0:owner xmlns:ns0="DAV:">
-        <ns0:href>http://www.apple.com/webdav_fs/</ns0:href>
-      </ns0:owner>
-        <D:timeout>{}</D:timeout>
-    </D:activelock>
-  </D:lockdiscovery>
-</D:prop>"#,
-        depth, timeout,
-    ))
+<multistatus xmlns="DAV:" xmlns:{}="{}">"#,
+        CUSTOM_PREFIX,
+        xml::escape(&config.custom_namespace)
+    )
 }
 
-type AsyncHandler = Box<dyn Fn(Request, Bucket) -> Pin<Box<dyn Future<Output = Result<Response>>>>>;
-
-async fn dispatch_request(req: Request, bucket: Bucket) -> Result<Response> {
-    let mut handlers: HashMap<&str, AsyncHandler> = HashMap::new();
-    handlers.insert(
-        "GET",
-        Box::new(|req, bucket| Box::pin(handle_get(req, bucket))),
-    );
-    handlers.insert(
-        "DELETE",
-        Box::new(|req, bucket| Box::pin(handle_delete(req, bucket))),
-    );
-    handlers.insert(
-        "PROPPATCH",
-        Box::new(|req, bucket| Box::pin(handle_proppatch(req, bucket))),
-    );
-    handlers.insert(
-        "PUT",
-        Box::new(|req, bucket| Box::pin(handle_put(req, bucket))),
-    );
-    handlers.insert(
-        "HEAD",
-        Box::new(|req, bucket| Box::pin(handle_head(req, bucket))),
-    );
-    handlers.insert(
-        "OPTIONS",
-        Box::new(|req, bucket| Box::pin(handle_options(req, bucket))),
-    );
-    handlers.insert(
-        "MKCOL",
-        Box::new(|req, bucket| Box::pin(handle_mkcol(req, bucket))),
-    );
-    handlers.insert(
-        "PROPFIND",
-        Box::new(|req, bucket| Box::pin(handle_propfind(req, bucket))),
-    );
-    handlers.insert(
-        "COPY",
-        Box::new(|req, bucket| Box::pin(handle_copy(req, bucket))),
-    );
-    handlers.insert(
-        "MOVE",
-        Box::new(|req, bucket| Box::pin(handle_move(req, bucket))),
-    );
-    handlers.insert(
-        "LOCK",
-        Box::new(|req, bucket| Box::pin(handle_lock(req, bucket))),
-    );
-    handlers.insert(
-        "UNLOCK",
-        Box::new(|_, _| Box::pin(async { Ok(Response::empty()?.with_status(204)) })),
-    );
+/// Wraps `<response>` elements in a `207 Multi-Status` document.
+fn multistatus(responses: String, config: &Config) -> Result<Response> {
+    let page = format!("{}{}</multistatus>", multistatus_start(config), responses);
+    let mut headers = Headers::new();
+    headers.append("Content-Type", XML_CONTENT_TYPE)?;
+    headers.append("Accept-Ranges", "none")?;
+    Ok(Response::ok(page)?.with_status(207).with_headers(headers))
+}
 
-    match handlers.get(req.method().as_str()) {
-        Some(handler) => handler(req, bucket).await,
+async fn dispatch_request(req: Request, bucket: Bucket, config: Config) -> Result<Response> {
+    if !path::is_mounted(&req.path()) {
+        return Response::error("Not Found", 404);
+    }
+    // Keys map to paths one to one, so the path alone decides whether R2
+    // could store it.
+    if path::is_too_long(&path::key_of(req.url()?.path())) {
+        return Response::error("URI Too Long: keys are limited to 1023 bytes", 414);
+    }
+    let method = req.method().as_str().to_string();
+    // Hidden keys, the worker's own locks among them, can't be written any
+    // more than read. What the trash and the versions keep is restored by
+    // COPY or MOVE, to wherever isn't hidden, and a DELETE in there removes
+    // it for good. POST reaches the `.dav/` endpoints and checks its own
+    // targets.
+    let key = path::key_of(req.url()?.path());
+    let reads = ["GET", "HEAD", "OPTIONS", "PROPFIND", "SEARCH", "POST"];
+    let restore = matches!(method.as_str(), "COPY" | "MOVE" | "DELETE") && config.is_kept(&key);
+    if !reads.contains(&method.as_str()) && config.is_hidden(&key) && !restore {
+        return Response::error("Not Found", 404);
+    }
+    match method.as_str() {
+        "GET" => handle_get(req, bucket, config).await,
+        "DELETE" => handle_delete(req, bucket, config).await,
+        "PROPPATCH" => handle_proppatch(req, bucket, config).await,
+        "PUT" => handle_put(req, bucket, config).await,
+        "HEAD" => handle_head(req, bucket, config).await,
+        "OPTIONS" => handle_options(req, bucket, config).await,
+        "MKCOL" => handle_mkcol(req, bucket, config).await,
+        "PROPFIND" => handle_propfind(req, bucket, config).await,
+        "COPY" => handle_copy(req, bucket, config).await,
+        "MOVE" => handle_move(req, bucket, config).await,
+        "LOCK" => handle_lock(req, bucket, config).await,
+        "POST" => handle_post(req, bucket, config).await,
+        "SEARCH" => handle_search(req, bucket, config).await,
+        "UNLOCK" => handle_unlock(req, bucket, config).await,
+        "BIND" if config.bind => handle_bind(req, bucket, config).await,
+        "UNBIND" if config.bind => handle_unbind(req, bucket, config).await,
+        "VERSION-CONTROL" if config.versioning => handle_version_control(req, bucket, config).await,
         _ => Response::error("Method Not allowed", 405),
     }
 }
 
-fn set_cors_headers(origin: Option<String>) -> Cors {
+/// Requests from origins outside `ALLOWED_ORIGINS` get no CORS headers, which
+/// makes the browser refuse the response.
+fn with_cors(res: Response, origin: Option<String>, config: &Config) -> Result<Response> {
+    match origin {
+        Some(_) => res.with_cors(&set_cors_headers(origin, config)),
+        None => Ok(res),
+    }
+}
+
+fn set_cors_headers(origin: Option<String>, config: &Config) -> Cors {
     let methods = METHODS
         .iter()
         .map(|s| Method::from_bytes(s.as_bytes()).expect("[ERROR] Invalid method"))
@@ -314,10 +2697,27 @@ fn set_cors_headers(origin: Option<String>) -> Cors {
         .with_origins(origin)
         .with_methods(methods)
         .with_allowed_headers(ALLOW_HEADERS)
-        .with_credentials(false)
         .with_exposed_headers(EXPOSED_HEADERS)
-        .with_max_age(86400)
-        .with_credentials(false)
+        .with_max_age(config.cors_max_age)
+        .with_credentials(config.cors_allow_credentials)
+}
+
+/// Some clients keep waiting for a body unless told there is none.
+fn empty_response(status: u16) -> Result<Response> {
+    let mut headers = Headers::new();
+    headers.append("Content-Length", "0")?;
+    Ok(Response::empty()?.with_status(status).with_headers(headers))
+}
+
+/// A body carrying `Content-Encoding` already holds encoded bytes, stored that
+/// way or a precompressed sidecar, so the runtime must send them untouched
+/// instead of compressing them a second time.
+fn as_stored(res: Response) -> Response {
+    if res.headers().has("Content-Encoding").unwrap_or(false) {
+        res.with_encode_body(EncodeBody::Manual)
+    } else {
+        res
+    }
 }
 
 fn get_headers(meta: HttpMetadata) -> Result<Headers> {
@@ -345,3 +2745,82 @@ fn get_headers(meta: HttpMetadata) -> Result<Headers> {
     }
     Ok(headers)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfers_between_nested_keys_overlap() {
+        assert!(overlaps("a", "a/b"));
+        assert!(overlaps("a", "a/b/c.txt"));
+        assert!(overlaps("a/b.txt", "a"));
+        assert!(overlaps("a/b/c", "a"));
+    }
+
+    #[test]
+    fn siblings_and_lookalikes_do_not_overlap() {
+        assert!(!overlaps("a", "ab"));
+        assert!(!overlaps("ab", "a"));
+        assert!(!overlaps("a/b", "a/c"));
+        assert!(!overlaps("a/b.txt", "b/a/b.txt"));
+    }
+
+    #[test]
+    fn tags_are_trimmed_and_joined_without_spaces() {
+        assert_eq!(normalize_tags("a=1, b=2"), "a=1,b=2");
+        assert_eq!(normalize_tags("  draft ,, ,final  "), "draft,final");
+        assert_eq!(normalize_tags(" , "), "");
+    }
+
+    #[test]
+    fn like_wildcards() {
+        assert!(like_match("%.pdf", "report.pdf"));
+        assert!(like_match("r_port%", "report-2024.txt"));
+        assert!(like_match("%", ""));
+        assert!(like_match("a%b%c", "abc"));
+        assert!(like_match("%a%b", "xaxbxab"));
+        assert!(like_match("_%_", "ab"));
+        assert!(!like_match("_", ""));
+        assert!(!like_match("%.pdf", "report.pdf.txt"));
+        assert!(!like_match("abc", "ab"));
+        assert!(!like_match("ab", "abc"));
+    }
+
+    #[test]
+    fn like_ignores_case() {
+        assert!(like_match("%.PDF", "Report.pdf"));
+        assert!(like_match("résumé%", "RÉSUMÉ.txt"));
+    }
+
+    #[test]
+    fn like_stays_fast_on_many_wildcards() {
+        let pattern = format!("{}b", "%a".repeat(30));
+        assert!(!like_match(&pattern, &"a".repeat(200)));
+        assert!(like_match(&pattern, &format!("{}b", "a".repeat(200))));
+    }
+
+    fn parts(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn weak_etag_is_stable_and_weak() {
+        let etag = weak_etag(parts(&["a.txt", "e1", "sub"]));
+        assert_eq!(etag, weak_etag(parts(&["a.txt", "e1", "sub"])));
+        assert!(etag.starts_with("W/\"") && etag.ends_with('"'));
+        assert_eq!(etag.len(), "W/\"\"".len() + 16);
+        assert_eq!(weak_etag(parts(&[])), "W/\"cbf29ce484222325\"");
+    }
+
+    #[test]
+    fn weak_etag_changes_with_any_part() {
+        let etag = weak_etag(parts(&["a.txt", "e1", "sub"]));
+        assert_ne!(etag, weak_etag(parts(&["a.txt", "e2", "sub"])));
+        assert_ne!(etag, weak_etag(parts(&["a.txt", "e1"])));
+        assert_ne!(
+            weak_etag(parts(&["ab", "c"])),
+            weak_etag(parts(&["a", "bc"]))
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! Write locks, kept as empty objects below `.dav/locks/` whose custom
+//! metadata describes the lock. A resource holds at most one lock; a second
+//! LOCK on it fails even when both would be shared.
+
+use std::collections::HashMap;
+use worker::{Bucket, Data, Date, Include, Result};
+
+use crate::config::INTERNAL_PREFIX;
+use crate::path::href;
+use crate::retry::retry;
+use crate::xml::escape;
+
+/// Granted for `Timeout: Infinite`, and the most any lock gets.
+const MAX_TIMEOUT_SECS: u64 = 24 * 60 * 60;
+/// Granted when the client doesn't ask for a timeout.
+const DEFAULT_TIMEOUT_SECS: u64 = 60 * 60;
+
+#[derive(Debug, Clone)]
+pub struct Lock {
+    /// Key of the locked resource.
+    pub root: String,
+    pub token: String,
+    pub exclusive: bool,
+    /// `Depth: infinity`, covering everything below a collection.
+    pub infinite: bool,
+    pub owner: String,
+    /// Expiry, in ms since the epoch.
+    pub expires: u64,
+}
+
+impl Lock {
+    pub fn new(root: &str, exclusive: bool, infinite: bool, owner: String, timeout: u64) -> Self {
+        Self {
+            root: root.to_string(),
+            token: format!("opaquelocktoken:{}", uuid::Uuid::new_v4()),
+            exclusive,
+            infinite,
+            owner,
+            expires: Date::now().as_millis() + timeout * 1000,
+        }
+    }
+
+    fn from_metadata(root: &str, metadata: &HashMap<String, String>) -> Option<Self> {
+        let lock = Self {
+            root: root.to_string(),
+            token: metadata.get("token")?.clone(),
+            exclusive: metadata.get("scope")? == "exclusive",
+            infinite: metadata.get("depth")? == "infinity",
+            owner: metadata.get("owner").cloned().unwrap_or_default(),
+            expires: metadata.get("expires")?.parse().ok()?,
+        };
+        (lock.expires > Date::now().as_millis()).then_some(lock)
+    }
+
+    fn to_metadata(&self) -> HashMap<String, String> {
+        HashMap::from([
+            (String::from("token"), self.token.clone()),
+            (String::from("scope"), String::from(self.scope())),
+            (String::from("depth"), String::from(self.depth())),
+            (String::from("owner"), self.owner.clone()),
+            (String::from("expires"), self.expires.to_string()),
+        ])
+    }
+
+    pub fn scope(&self) -> &'static str {
+        if self.exclusive {
+            "exclusive"
+        } else {
+            "shared"
+        }
+    }
+
+    pub fn depth(&self) -> &'static str {
+        if self.infinite {
+            "infinity"
+        } else {
+            "0"
+        }
+    }
+
+    pub fn refresh(&mut self, timeout: u64) {
+        self.expires = Date::now().as_millis() + timeout * 1000;
+    }
+
+    /// The `<activelock>` element reported in `lockdiscovery`.
+    pub fn activelock(&self) -> String {
+        let remaining = self.expires.saturating_sub(Date::now().as_millis()) / 1000;
+        format!(
+            "<activelock><locktype><write/></locktype><lockscope><{}/></lockscope><depth>{}</depth><owner>{}</owner><timeout>Second-{}</timeout><locktoken><href>{}</href></locktoken><lockroot><href>{}</href></lockroot></activelock>",
+            self.scope(),
+            self.depth(),
+            escape(&self.owner),
+            remaining,
+            self.token,
+            escape(&href(&self.root))
+        )
+    }
+}
+
+/// The granted lifetime for a `Timeout` header such as `Second-600, Infinite`;
+/// the first value understood wins.
+pub fn timeout_secs(header: Option<&str>) -> u64 {
+    let header = match header {
+        Some(header) => header,
+        None => return DEFAULT_TIMEOUT_SECS,
+    };
+    for value in header.split(',').map(str::trim) {
+        if value.eq_ignore_ascii_case("Infinite") {
+            return MAX_TIMEOUT_SECS;
+        }
+        if let Some(secs) = value.strip_prefix("Second-").and_then(|s| s.parse().ok()) {
+            return MAX_TIMEOUT_SECS.min(secs);
+        }
+    }
+    DEFAULT_TIMEOUT_SECS
+}
+
+fn lock_key(key: &str) -> String {
+    format!("{}/locks/{}", INTERNAL_PREFIX, key)
+}
+
+/// The unexpired lock on `key` itself, if any.
+pub async fn load(bucket: &Bucket, key: &str) -> Result<Option<Lock>> {
+    let object = match retry(|| bucket.head(lock_key(key))).await? {
+        Some(object) => object,
+        None => return Ok(None),
+    };
+    Ok(Lock::from_metadata(key, &object.custom_metadata()?))
+}
+
+/// The lock that covers `key`: its own, or a `Depth: infinity` lock on one of
+/// its ancestors, the root included.
+pub async fn covering(bucket: &Bucket, key: &str) -> Result<Option<Lock>> {
+    if let Some(lock) = load(bucket, key).await? {
+        return Ok(Some(lock));
+    }
+    let mut ancestor = key;
+    while !ancestor.is_empty() {
+        ancestor = ancestor.rsplit_once('/').map_or("", |(parent, _)| parent);
+        if let Some(lock) = load(bucket, ancestor).await? {
+            if lock.infinite {
+                return Ok(Some(lock));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Every unexpired lock on `prefix` and below, by locked key.
+pub async fn list_under(bucket: &Bucket, prefix: &str) -> Result<HashMap<String, Lock>> {
+    let lock_prefix = lock_key(prefix);
+    let mut locks = HashMap::new();
+    let mut cursor = None;
+    loop {
+        let objects = retry(|| {
+            let mut list_req = bucket
+                .list()
+                .prefix(lock_prefix.clone())
+                .include(vec![Include::CustomMetadata]);
+            if let Some(c) = cursor.clone() {
+                list_req = list_req.cursor(c);
+            }
+            list_req.execute()
+        })
+        .await?;
+        for object in objects.objects() {
+            let key = object.key()[lock_key("").len()..].to_string();
+            if let Some(lock) = Lock::from_metadata(&key, &object.custom_metadata()?) {
+                locks.insert(key, lock);
+            }
+        }
+        if !objects.truncated() {
+            break;
+        }
+        cursor = objects.cursor();
+    }
+    Ok(locks)
+}
+
+pub async fn store(bucket: &Bucket, lock: &Lock) -> Result<()> {
+    bucket
+        .put(lock_key(&lock.root), Data::from(String::new()))
+        .custom_metadata(lock.to_metadata())
+        .execute()
+        .await?;
+    Ok(())
+}
+
+pub async fn remove(bucket: &Bucket, key: &str) -> Result<()> {
+    retry(|| bucket.delete(lock_key(key))).await
+}
+
+/// Drops the locks on `key` and below once the resources are gone.
+pub async fn remove_all(bucket: &Bucket, key: &str) -> Result<()> {
+    let prefix = if key.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", key)
+    };
+    for member in list_under(bucket, &prefix).await?.into_keys() {
+        remove(bucket, &member).await?;
+    }
+    remove(bucket, key).await
+}
+
+/// The lock tokens a request submits in its `If` header.
+pub fn submitted_tokens(if_header: &str) -> Vec<String> {
+    if_header
+        .split('<')
+        .filter_map(|part| part.split_once('>'))
+        .map(|(token, _)| token.trim().to_string())
+        .filter(|token| token.starts_with("opaquelocktoken:"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_takes_the_first_value_understood() {
+        assert_eq!(timeout_secs(None), DEFAULT_TIMEOUT_SECS);
+        assert_eq!(timeout_secs(Some("Second-600")), 600);
+        assert_eq!(timeout_secs(Some("Extended, Second-60, Infinite")), 60);
+        assert_eq!(timeout_secs(Some("infinite, Second-60")), MAX_TIMEOUT_SECS);
+        assert_eq!(timeout_secs(Some("Second-4100000000")), MAX_TIMEOUT_SECS);
+        assert_eq!(timeout_secs(Some("Second-soon")), DEFAULT_TIMEOUT_SECS);
+    }
+
+    #[test]
+    fn only_lock_tokens_are_submitted() {
+        assert_eq!(
+            submitted_tokens(
+                "</docs/a.txt> (<opaquelocktoken:abc> [\"etag\"]) (Not <opaquelocktoken:def>)"
+            ),
+            ["opaquelocktoken:abc", "opaquelocktoken:def"]
+        );
+        assert!(submitted_tokens("([\"etag\"])").is_empty());
+    }
+
+    #[test]
+    fn depth_and_scope_follow_the_flags() {
+        let mut lock = Lock {
+            root: String::from("docs"),
+            token: String::from("opaquelocktoken:abc"),
+            exclusive: true,
+            infinite: true,
+            owner: String::new(),
+            expires: 0,
+        };
+        assert_eq!((lock.scope(), lock.depth()), ("exclusive", "infinity"));
+        lock.exclusive = false;
+        lock.infinite = false;
+        assert_eq!((lock.scope(), lock.depth()), ("shared", "0"));
+    }
+}
@@ -0,0 +1,212 @@
+use std::cell::RefCell;
+
+thread_local! {
+    /// `MOUNT_PATH`, as `/dav` or empty at the root; set per request.
+    static MOUNT: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Sets the path the worker is routed under, e.g. `/dav` for
+/// `example.com/dav/*`.
+pub fn set_mount(mount: &str) {
+    MOUNT.with(|m| *m.borrow_mut() = mount.to_string());
+}
+
+/// Whether a request path lies below the mount path.
+pub fn is_mounted(path: &str) -> bool {
+    MOUNT.with(|m| {
+        let mount = m.borrow();
+        path.strip_prefix(mount.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// The key a request path addresses, the mount path left out.
+pub fn key_of(path: &str) -> String {
+    MOUNT.with(|m| normalize_key(path.strip_prefix(m.borrow().as_str()).unwrap_or(path)))
+}
+
+/// The key `path` addressed before keys were spelled one way: its segments
+/// as they arrived, escapes untouched, e.g. `r%c3%a9sum%c3%a9.pdf`. `None`
+/// when that is the canonical key anyway. Objects stored under such a key
+/// stay reachable by it until a MOVE gives them their canonical name.
+pub fn legacy_key_of(path: &str) -> Option<String> {
+    MOUNT.with(|m| {
+        let path = path.strip_prefix(m.borrow().as_str()).unwrap_or(path);
+        let mut segments = vec![];
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                segment => segments.push(segment),
+            }
+        }
+        let legacy = segments.join("/");
+        (legacy != normalize_key(path)).then_some(legacy)
+    })
+}
+
+/// The absolute path clients use for `key`: `/{key}` below the mount path.
+pub fn href(key: &str) -> String {
+    MOUNT.with(|m| format!("{}/{}", m.borrow(), key))
+}
+
+/// Canonical R2 key for a request path.
+///
+/// Empty and `.` segments are dropped and `..` pops its parent, so `/a//b`,
+/// `/a/./b` and `/a/b/` all address `a/b`.
+///
+/// Keys stay percent-encoded, as paths arrive, but are spelled one way:
+/// `résumé.pdf`, `r%c3%a9sum%c3%a9.pdf` and `r%C3%A9sum%C3%A9.pdf` are all
+/// `r%C3%A9sum%C3%A9.pdf`. A name therefore survives a round trip through
+/// `Destination`, an href or a JSON key whichever form the client sends.
+pub fn normalize_key(path: &str) -> String {
+    let mut segments = vec![];
+    for segment in path.split('/') {
+        match canonical_segment(segment).as_str() {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment.to_string()),
+        }
+    }
+    segments.join("/")
+}
+
+/// Decodes every escape and encodes again exactly what a URL parser encodes
+/// in a path, with uppercase hex. An escaped `/` or `%` stays escaped, and a
+/// `%` that starts no escape is kept as written.
+fn canonical_segment(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut canonical = String::with_capacity(segment.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let (byte, width) = match escape_at(bytes, i) {
+            Some(byte) => (byte, 3),
+            None if bytes[i] == b'%' => {
+                canonical.push('%');
+                i += 1;
+                continue;
+            }
+            None => (bytes[i], 1),
+        };
+        i += width;
+        match byte {
+            b'"' | b'#' | b'<' | b'>' | b'?' | b'`' | b'{' | b'}' | b'%' | b'/' => {
+                canonical.push_str(&format!("%{:02X}", byte))
+            }
+            0x21..=0x7e => canonical.push(byte as char),
+            _ => canonical.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    canonical
+}
+
+/// The readable form of a key or segment, for listings shown to people.
+pub fn decode(key: &str) -> String {
+    let bytes = key.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match escape_at(bytes, i) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The byte a `%XX` escape at `i` stands for.
+fn escape_at(bytes: &[u8], i: usize) -> Option<u8> {
+    match bytes.get(i..i + 3)? {
+        [b'%', hi, lo] if hi.is_ascii_hexdigit() && lo.is_ascii_hexdigit() => {
+            let hex = |digit: u8| (digit as char).to_digit(16).unwrap_or_default() as u8;
+            Some(hex(*hi) << 4 | hex(*lo))
+        }
+        _ => None,
+    }
+}
+
+/// R2 refuses keys longer than 1024 bytes.
+const MAX_KEY_LEN: usize = 1024;
+
+/// Whether `key` is too long to store. One byte is kept in reserve so the
+/// key can still become a collection, whose marker adds a trailing `/`.
+pub fn is_too_long(key: &str) -> bool {
+    key.len() >= MAX_KEY_LEN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_and_dot_segments_collapse() {
+        assert_eq!(normalize_key("/a//b"), "a/b");
+        assert_eq!(normalize_key("/a/./b"), "a/b");
+        assert_eq!(normalize_key("/a/b/"), "a/b");
+        assert_eq!(normalize_key("/a/../b"), "b");
+        assert_eq!(normalize_key("/../a"), "a");
+        assert_eq!(normalize_key("/"), "");
+    }
+
+    #[test]
+    fn key_length_limit() {
+        assert!(!is_too_long(&"a".repeat(MAX_KEY_LEN - 1)));
+        assert!(is_too_long(&"a".repeat(MAX_KEY_LEN)));
+    }
+
+    #[test]
+    fn escapes_are_spelled_one_way() {
+        let canonical = "r%C3%A9sum%C3%A9.pdf";
+        assert_eq!(normalize_key("/résumé.pdf"), canonical);
+        assert_eq!(normalize_key("/r%c3%a9sum%c3%a9.pdf"), canonical);
+        assert_eq!(normalize_key("/r%C3%A9sum%C3%A9.pdf"), canonical);
+        assert_eq!(normalize_key("/a b"), "a%20b");
+        assert_eq!(normalize_key("/%41%7e"), "A~");
+    }
+
+    #[test]
+    fn escaped_separators_and_stray_percents_stay() {
+        assert_eq!(normalize_key("/a%2fb"), "a%2Fb");
+        assert_eq!(normalize_key("/100%25"), "100%25");
+        assert_eq!(normalize_key("/100%"), "100%");
+        assert_eq!(normalize_key("/%zz"), "%zz");
+        assert_eq!(normalize_key("/a/%2e%2e/b"), "b");
+    }
+
+    #[test]
+    fn decode_for_display() {
+        assert_eq!(decode("r%C3%A9sum%C3%A9.pdf"), "résumé.pdf");
+        assert_eq!(decode("a%2Fb"), "a/b");
+        assert_eq!(decode("100%"), "100%");
+    }
+
+    #[test]
+    fn legacy_spelling() {
+        assert_eq!(
+            legacy_key_of("/r%c3%a9sum%c3%a9.pdf").as_deref(),
+            Some("r%c3%a9sum%c3%a9.pdf")
+        );
+        assert_eq!(legacy_key_of("/a//b/"), None);
+    }
+
+    #[test]
+    fn mount_path() {
+        set_mount("/dav");
+        assert!(is_mounted("/dav"));
+        assert!(is_mounted("/dav/a"));
+        assert!(!is_mounted("/davx"));
+        assert_eq!(key_of("/dav/a/b"), "a/b");
+        assert_eq!(href("a/b"), "/dav/a/b");
+        set_mount("");
+    }
+}
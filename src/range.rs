@@ -0,0 +1,232 @@
+/// A satisfiable byte range, inclusive of both ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, size)
+    }
+}
+
+/// Most slices one `Range` header may ask for; past it the whole object is
+/// served, so a request can't fan out into a flood of R2 reads.
+const MAX_RANGES: usize = 8;
+/// Most bytes a multipart answer buffers across all of its slices.
+const MAX_MULTIPART_LENGTH: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RangeRequest {
+    /// The header doesn't apply and the whole object is served.
+    Full,
+    Partial(ByteRange),
+    /// Several slices, sent as `multipart/byteranges` in the order asked.
+    Multiple(Vec<ByteRange>),
+    Unsatisfiable,
+}
+
+/// [Range header](https://www.rfc-editor.org/rfc/rfc9110#name-range)
+///
+/// Each `bytes` range is resolved on its own; for a 1000 byte object:
+///
+/// - `bytes=500-` is `500-999`; an open end always stops at the last byte.
+/// - `bytes=-500` is `500-999`, and `bytes=-5000` clamps to all of `0-999`.
+/// - `bytes=500-5000` is cut to `500-999`; `bytes=1000-` is unsatisfiable.
+///
+/// A list such as `bytes=0-99,200-299` drops its unsatisfiable members and is
+/// unsatisfiable only when none is left. A header that doesn't parse, or asks
+/// for more than `MAX_RANGES` slices or `MAX_MULTIPART_LENGTH` bytes, is
+/// ignored, which the spec allows, and the full object is served instead.
+///
+/// An empty object has no bytes to select, so every range on it, the suffix
+/// form included, is unsatisfiable.
+pub fn parse(header: &str, size: u64) -> RangeRequest {
+    let spec = match header.trim().split_once('=') {
+        Some((unit, spec)) if unit.trim().eq_ignore_ascii_case("bytes") => spec.trim(),
+        _ => return RangeRequest::Full,
+    };
+    let specs = spec.split(',').map(str::trim).collect::<Vec<_>>();
+    if specs.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+    let mut ranges = vec![];
+    for spec in specs {
+        match parse_one(spec, size) {
+            Some(Some(range)) => ranges.push(range),
+            Some(None) => {}
+            None => return RangeRequest::Full,
+        }
+    }
+    match ranges.len() {
+        0 => RangeRequest::Unsatisfiable,
+        1 => RangeRequest::Partial(ranges[0]),
+        _ if ranges.iter().map(ByteRange::length).sum::<u64>() > MAX_MULTIPART_LENGTH => {
+            RangeRequest::Full
+        }
+        _ => RangeRequest::Multiple(ranges),
+    }
+}
+
+/// One `first-last` member of a range list: `None` when it doesn't parse,
+/// `Some(None)` when it selects nothing of the object.
+fn parse_one(spec: &str, size: u64) -> Option<Option<ByteRange>> {
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+
+    if first.is_empty() {
+        // suffix form: the last `n` bytes
+        return match last.parse::<u64>().ok()? {
+            0 => Some(None),
+            _ if size == 0 => Some(None),
+            n => Some(Some(ByteRange {
+                start: size.saturating_sub(n),
+                end: size - 1,
+            })),
+        };
+    }
+
+    let start = first.parse::<u64>().ok()?;
+    let end = if last.is_empty() {
+        None
+    } else {
+        match last.parse::<u64>() {
+            Ok(end) if end >= start => Some(end),
+            _ => return None,
+        }
+    };
+    if start >= size {
+        return Some(None);
+    }
+    Some(Some(ByteRange {
+        start,
+        end: end.map_or(size - 1, |end| end.min(size - 1)),
+    }))
+}
+
+/// [If-Range](https://www.rfc-editor.org/rfc/rfc9110#name-if-range)
+///
+/// The range is only honored while the client's entity tag still names the
+/// current object. R2 tags of multipart uploads end in `-{parts}` and are not
+/// a digest of the body, so they are compared as opaque strings, never parsed.
+/// Weak tags and dates never match, which falls back to the full object.
+pub fn if_range_matches(header: &str, etag: &str) -> bool {
+    let header = header.trim();
+    header.starts_with('"') && header == etag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn open_and_suffix_ranges() {
+        assert_eq!(
+            parse("bytes=500-", 1000),
+            RangeRequest::Partial(range(500, 999))
+        );
+        assert_eq!(
+            parse("bytes=-500", 1000),
+            RangeRequest::Partial(range(500, 999))
+        );
+        assert_eq!(
+            parse("bytes=-5000", 1000),
+            RangeRequest::Partial(range(0, 999))
+        );
+    }
+
+    #[test]
+    fn end_is_clamped_to_the_object() {
+        assert_eq!(
+            parse("bytes=500-5000", 1000),
+            RangeRequest::Partial(range(500, 999))
+        );
+        assert_eq!(parse("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn unit_and_spacing_are_lenient() {
+        assert_eq!(
+            parse(" Bytes = 0-9 ", 1000),
+            RangeRequest::Partial(range(0, 9))
+        );
+    }
+
+    #[test]
+    fn malformed_headers_serve_everything() {
+        assert_eq!(parse("items=0-9", 1000), RangeRequest::Full);
+        assert_eq!(parse("bytes=abc", 1000), RangeRequest::Full);
+        assert_eq!(parse("bytes=9-0", 1000), RangeRequest::Full);
+        assert_eq!(parse("bytes=0-9,x", 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn empty_objects_satisfy_nothing() {
+        assert_eq!(parse("bytes=0-", 0), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=-5", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn content_range_and_length() {
+        let r = range(500, 999);
+        assert_eq!(r.length(), 500);
+        assert_eq!(r.content_range(1000), "bytes 500-999/1000");
+    }
+
+    #[test]
+    fn if_range_needs_the_same_strong_tag() {
+        assert!(if_range_matches(" \"abc\" ", "\"abc\""));
+        assert!(if_range_matches("\"abc-3\"", "\"abc-3\""));
+        assert!(!if_range_matches("\"abd\"", "\"abc\""));
+        assert!(!if_range_matches("W/\"abc\"", "\"abc\""));
+        assert!(!if_range_matches(
+            "Wed, 21 Oct 2015 07:28:00 GMT",
+            "\"abc\""
+        ));
+    }
+
+    #[test]
+    fn lists_drop_unsatisfiable_members() {
+        assert_eq!(
+            parse("bytes=0-99, 200-299", 1000),
+            RangeRequest::Multiple(vec![range(0, 99), range(200, 299)])
+        );
+        assert_eq!(
+            parse("bytes=0-99,2000-2999", 1000),
+            RangeRequest::Partial(range(0, 99))
+        );
+        assert_eq!(
+            parse("bytes=2000-,3000-", 1000),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn too_many_slices_serve_everything() {
+        let header = format!(
+            "bytes={}",
+            (0..=MAX_RANGES)
+                .map(|i| format!("{}-{}", i * 10, i * 10 + 1))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        assert_eq!(parse(&header, 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn oversized_multipart_serves_everything() {
+        let half = MAX_MULTIPART_LENGTH / 2 + 1;
+        let header = format!("bytes=0-{},{}-{}", half - 1, half, 2 * half - 1);
+        assert_eq!(parse(&header, 4 * MAX_MULTIPART_LENGTH), RangeRequest::Full);
+    }
+}
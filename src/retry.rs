@@ -0,0 +1,182 @@
+use std::{cell::Cell, future::Future, time::Duration};
+use worker::{Date, Delay, Error, Result};
+
+/// Calls that still fail after their retries, within `BREAKER_WINDOW_MS`,
+/// before the breaker opens.
+const BREAKER_THRESHOLD: u32 = 5;
+const BREAKER_WINDOW_MS: u64 = 30_000;
+/// How long an open breaker turns requests away.
+const BREAKER_COOLDOWN_MS: u64 = 30_000;
+
+thread_local! {
+    /// Extra tries after a transient failure; set from `RETRY_ATTEMPTS` per
+    /// request. Every request in an isolate shares the same env.
+    static ATTEMPTS: Cell<u32> = const { Cell::new(2) };
+    /// The isolate's circuit breaker: failures counted since the start of the
+    /// current window, and until when it stays open.
+    static BREAKER: Cell<Breaker> = const {
+        Cell::new(Breaker { failures: 0, window_start: 0, open_until: 0 })
+    };
+}
+
+#[derive(Clone, Copy)]
+struct Breaker {
+    failures: u32,
+    window_start: u64,
+    open_until: u64,
+}
+
+impl Breaker {
+    /// The breaker after a call that failed for good at `now`.
+    fn failed(mut self, now: u64) -> Self {
+        if now - self.window_start > BREAKER_WINDOW_MS {
+            self.failures = 0;
+            self.window_start = now;
+        }
+        self.failures += 1;
+        if self.failures >= BREAKER_THRESHOLD {
+            self.open_until = now + BREAKER_COOLDOWN_MS;
+            self.failures = 0;
+        }
+        self
+    }
+
+    /// The breaker after a call that went through.
+    fn succeeded(mut self) -> Self {
+        self.failures = 0;
+        self
+    }
+
+    /// Whole seconds, rounded up, that the breaker stays open after `now`.
+    fn open_for(self, now: u64) -> Option<u64> {
+        (self.open_until > now).then(|| (self.open_until - now).div_ceil(1000))
+    }
+}
+
+pub fn set_attempts(attempts: u32) {
+    ATTEMPTS.with(|a| a.set(attempts));
+}
+
+/// Runs an idempotent storage call again after a transient failure, waiting
+/// 50ms, 100ms, 200ms... in between. Anything else fails right away.
+pub async fn retry<T, F, Fut>(mut op: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let attempts = ATTEMPTS.with(Cell::get);
+    for n in 0..attempts {
+        match op().await {
+            Err(e) if is_transient(&e) => {
+                Delay::from(Duration::from_millis(backoff_ms(n))).await;
+            }
+            result => return record(result),
+        }
+    }
+    record(op().await)
+}
+
+/// The wait before retry `n`, counting from 0: 50ms, doubling each time.
+fn backoff_ms(n: u32) -> u64 {
+    50u64.saturating_mul(2u64.saturating_pow(n))
+}
+
+/// Counts a call that failed for good towards opening the breaker; any
+/// success closes it again.
+fn record<T>(result: Result<T>) -> Result<T> {
+    BREAKER.with(|cell| match &result {
+        Ok(_) => cell.set(cell.get().succeeded()),
+        Err(e) if is_transient(e) => cell.set(cell.get().failed(Date::now().as_millis())),
+        Err(_) => {}
+    });
+    result
+}
+
+/// Seconds left while R2 keeps failing and requests are better turned away
+/// with `503`, so an outage isn't made worse by every client retrying
+/// through. Each isolate keeps its own breaker.
+pub fn open_for() -> Option<u64> {
+    BREAKER.with(|cell| cell.get().open_for(Date::now().as_millis()))
+}
+
+/// The binding reports R2 failures only as messages; these are the ones that
+/// say nothing about the request itself.
+fn is_transient(err: &Error) -> bool {
+    let message = err.to_string().to_lowercase();
+    [
+        "internal error",
+        "network",
+        "timed out",
+        "try again",
+        "service unavailable",
+        "connection",
+    ]
+    .iter()
+    .any(|m| message.contains(m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_from_50ms() {
+        assert_eq!(backoff_ms(0), 50);
+        assert_eq!(backoff_ms(1), 100);
+        assert_eq!(backoff_ms(2), 200);
+        assert_eq!(backoff_ms(100), u64::MAX);
+    }
+
+    #[test]
+    fn only_failures_of_the_storage_are_transient() {
+        for message in [
+            "Internal error",
+            "Network connection lost",
+            "The operation timed out",
+            "Please try again later",
+            "Service Unavailable",
+        ] {
+            assert!(is_transient(&Error::from(message)), "{}", message);
+        }
+        for message in ["Object Not Found", "PreconditionFailed", "Body is None"] {
+            assert!(!is_transient(&Error::from(message)), "{}", message);
+        }
+    }
+
+    const CLOSED: Breaker = Breaker {
+        failures: 0,
+        window_start: 0,
+        open_until: 0,
+    };
+
+    fn failures(times: &[u64]) -> Breaker {
+        times.iter().fold(CLOSED, |b, &now| b.failed(now))
+    }
+
+    #[test]
+    fn breaker_opens_at_the_threshold_for_the_cooldown() {
+        let start = 1_000_000;
+        let breaker = failures(&[start; 4]);
+        assert_eq!(breaker.open_for(start), None);
+        let breaker = breaker.failed(start);
+        assert_eq!(breaker.open_for(start), Some(30));
+        assert_eq!(breaker.open_for(start + 29_001), Some(1));
+        assert_eq!(breaker.open_for(start + BREAKER_COOLDOWN_MS), None);
+    }
+
+    #[test]
+    fn breaker_forgets_failures_outside_the_window() {
+        let start = 1_000_000;
+        let breaker = failures(&[start; 4]).failed(start + BREAKER_WINDOW_MS + 1);
+        assert_eq!(breaker.failures, 1);
+        assert_eq!(breaker.open_for(start + BREAKER_WINDOW_MS + 1), None);
+    }
+
+    #[test]
+    fn success_resets_the_count() {
+        let start = 1_000_000;
+        let breaker = failures(&[start; 4]).succeeded().failed(start);
+        assert_eq!(breaker.failures, 1);
+        assert_eq!(breaker.open_for(start), None);
+    }
+}
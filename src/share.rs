@@ -0,0 +1,108 @@
+//! Time-limited share links: a GET URL carrying an expiry and an HMAC-SHA256
+//! signature over the path and that expiry, keyed with `SIGNING_SECRET`, is
+//! served without credentials until it expires. The path is signed as sent,
+//! trailing slash included, so a link to the file `docs` doesn't also open
+//! `docs/` and its listing.
+
+use worker::{Date, Url};
+
+use crate::s3::{hex, hmac};
+
+/// Lifetime of a link minted without `expires`, in seconds.
+pub const DEFAULT_EXPIRES_SECS: u64 = 60 * 60;
+/// Longest lifetime a link can be minted with.
+pub const MAX_EXPIRES_SECS: u64 = 7 * 24 * 60 * 60;
+
+fn signature(secret: &str, path: &str, expires: u64) -> String {
+    hex(&hmac(
+        secret.as_bytes(),
+        format!("{}\n{}", path, expires).as_bytes(),
+    ))
+}
+
+/// Replaces the query of `url` with `expires`, in seconds since the epoch,
+/// and the signature binding it to the path of `url`.
+pub fn sign(secret: &str, url: &mut Url, expires: u64) {
+    let signature = signature(secret, url.path(), expires);
+    url.query_pairs_mut()
+        .clear()
+        .append_pair("expires", &expires.to_string())
+        .append_pair("signature", &signature);
+}
+
+/// `None` when `url` isn't a share link at all, otherwise whether its
+/// signature is intact and not yet expired.
+pub fn verify(secret: &str, url: &Url) -> Option<bool> {
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    };
+    let given = param("signature")?;
+    let expires = match param("expires").and_then(|e| e.parse::<u64>().ok()) {
+        Some(expires) => expires,
+        None => return Some(false),
+    };
+    if expires.saturating_mul(1000) <= Date::now().as_millis() {
+        return Some(false);
+    }
+    let expected = signature(secret, url.path(), expires);
+    // Compared in constant time so the signature can't be found byte by byte.
+    let difference = given
+        .bytes()
+        .zip(expected.bytes())
+        .fold(0, |acc, (a, b)| acc | (a ^ b));
+    Some(given.len() == expected.len() && difference == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(url: &Url, name: &str) -> Option<String> {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    }
+
+    #[test]
+    fn sign_replaces_the_query() {
+        let mut url = Url::parse("https://example.com/docs/a.pdf?download=1").unwrap();
+        sign("secret", &mut url, 1_700_000_000);
+        assert_eq!(param(&url, "download"), None);
+        assert_eq!(param(&url, "expires").as_deref(), Some("1700000000"));
+        assert_eq!(
+            param(&url, "signature"),
+            Some(signature("secret", "/docs/a.pdf", 1_700_000_000))
+        );
+    }
+
+    #[test]
+    fn signature_binds_path_expiry_and_secret() {
+        let base = signature("secret", "/docs", 1);
+        assert_eq!(base.len(), 64);
+        assert_eq!(base, signature("secret", "/docs", 1));
+        assert_ne!(base, signature("secret", "/docs/", 1));
+        assert_ne!(base, signature("secret", "/docs", 2));
+        assert_ne!(base, signature("other", "/docs", 1));
+    }
+
+    #[test]
+    fn verify_skips_plain_urls() {
+        let url = Url::parse("https://example.com/docs?expires=1").unwrap();
+        assert_eq!(verify("secret", &url), None);
+    }
+
+    #[test]
+    fn verify_refuses_a_missing_expiry() {
+        let mut url = Url::parse("https://example.com/docs").unwrap();
+        sign("secret", &mut url, 1);
+        let signature = param(&url, "signature").unwrap();
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("signature", &signature);
+        assert_eq!(verify("secret", &url), Some(false));
+        url.query_pairs_mut().append_pair("expires", "soon");
+        assert_eq!(verify("secret", &url), Some(false));
+    }
+}
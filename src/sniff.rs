@@ -0,0 +1,68 @@
+/// How much of the object is read to guess its type.
+pub const SNIFF_LEN: u64 = 512;
+
+const SIGNATURES: [(&[u8], &str); 5] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"%PDF-", "application/pdf"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"GIF8", "image/gif"),
+];
+
+/// Guesses a content type from the leading bytes of an object.
+pub fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    if let Some((_, mime)) = SIGNATURES
+        .iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+    {
+        return Some(mime);
+    }
+    if !bytes.is_empty() && is_text(bytes) {
+        return Some("text/plain; charset=utf-8");
+    }
+    None
+}
+
+fn is_text(bytes: &[u8]) -> bool {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        // The sample may end in the middle of a multi-byte character.
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return false,
+    };
+    !text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_win_over_text() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\nrest"), Some("image/png"));
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0"), Some("image/jpeg"));
+        assert_eq!(sniff(b"%PDF-1.7\n"), Some("application/pdf"));
+        assert_eq!(sniff(b"\x1f\x8b\x08"), Some("application/gzip"));
+        assert_eq!(sniff(b"GIF89a"), Some("image/gif"));
+    }
+
+    #[test]
+    fn printable_utf8_is_text() {
+        let text = Some("text/plain; charset=utf-8");
+        assert_eq!(sniff(b"hello\tworld\r\n\x0c"), text);
+        assert_eq!(sniff("caf\u{e9}".as_bytes()), text);
+        // A sample cut inside the two-byte `é`.
+        assert_eq!(sniff(&"caf\u{e9}".as_bytes()[..4]), text);
+    }
+
+    #[test]
+    fn binary_and_empty_samples_are_unknown() {
+        assert_eq!(sniff(b""), None);
+        assert_eq!(sniff(b"abc\x00def"), None);
+        assert_eq!(sniff(b"abc\xffdef"), None);
+    }
+}
@@ -0,0 +1,22 @@
+use std::{future::Future, pin::pin, task::Poll, time::Duration};
+use worker::Delay;
+
+/// Resolves to `None` when `future` hasn't finished within `ms` milliseconds;
+/// `0` waits forever.
+pub async fn with_deadline<F: Future>(future: F, ms: u64) -> Option<F::Output> {
+    if ms == 0 {
+        return Some(future.await);
+    }
+    let mut future = pin!(future);
+    let mut delay = pin!(Delay::from(Duration::from_millis(ms)));
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        if delay.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    })
+    .await
+}
@@ -0,0 +1,366 @@
+//! Just enough XML reading for DAV request bodies; element names are matched
+//! by local name so any namespace prefix is accepted. Only PROPPATCH resolves
+//! namespaces, as the properties it stores are told apart by them.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token<'a> {
+    /// Tag name and whether the element is self-closing.
+    Start(&'a str, bool),
+    End(&'a str),
+    Text(&'a str),
+}
+
+/// Splits a document into tags and text, skipping declarations, comments and
+/// processing instructions. Returns `None` on an unterminated construct.
+pub fn tokenize(body: &str) -> Option<Vec<Token<'_>>> {
+    Some(
+        tokenize_with_attributes(body)?
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect(),
+    )
+}
+
+/// Like `tokenize`, pairing each start tag with the attributes written after
+/// its name; every other token comes with none.
+fn tokenize_with_attributes(body: &str) -> Option<Vec<(Token<'_>, &str)>> {
+    let mut tokens = vec![];
+    let mut rest = body;
+    while let Some(start) = rest.find('<') {
+        if start > 0 {
+            tokens.push((Token::Text(&rest[..start]), ""));
+        }
+        rest = &rest[start..];
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>")?;
+            tokens.push((Token::Text(&cdata[..end]), ""));
+            rest = &cdata[end + 3..];
+            continue;
+        }
+        let terminator = if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<?") {
+            "?>"
+        } else {
+            ">"
+        };
+        let end = rest.find(terminator)? + terminator.len();
+        let tag = &rest[1..end - 1];
+        rest = &rest[end..];
+        if tag.starts_with('!') || tag.starts_with('?') {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            tokens.push((Token::End(name.trim()), ""));
+        } else {
+            let self_closing = tag.ends_with('/');
+            let (name, attributes) = tag
+                .trim_end_matches('/')
+                .split_once(char::is_whitespace)
+                .unwrap_or((tag.trim_end_matches('/'), ""));
+            tokens.push((Token::Start(name, self_closing), attributes));
+        }
+    }
+    if !rest.is_empty() {
+        tokens.push((Token::Text(rest), ""));
+    }
+    Some(tokens)
+}
+
+/// The `name="value"` pairs of a start tag, values unescaped. Reading stops
+/// at the first pair that isn't one.
+fn attributes(mut rest: &str) -> Vec<(&str, String)> {
+    let mut attributes = vec![];
+    while let Some((name, value)) = rest.split_once('=') {
+        let value = value.trim_start();
+        let quote = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => break,
+        };
+        let end = match value[1..].find(quote) {
+            Some(end) => end + 1,
+            None => break,
+        };
+        attributes.push((name.trim(), unescape(&value[1..end])));
+        rest = &value[end + 1..];
+    }
+    attributes
+}
+
+/// Whether `name` can name an element: a letter or `_`, then letters,
+/// digits, `-`, `_` and `.`.
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+pub fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// The trimmed, unescaped text of the first element named `name`.
+pub fn element_text(body: &str, name: &str) -> Option<String> {
+    let tokens = tokenize(body)?;
+    let start = tokens
+        .iter()
+        .position(|t| matches!(t, Token::Start(n, _) if local_name(n) == name))?;
+    if let Token::Start(_, true) = tokens[start] {
+        return Some(String::new());
+    }
+    let mut depth = 0;
+    let mut text = String::new();
+    for token in &tokens[start + 1..] {
+        match token {
+            Token::Start(_, false) => depth += 1,
+            Token::End(_) if depth == 0 => break,
+            Token::End(_) => depth -= 1,
+            Token::Text(t) => text.push_str(t),
+            Token::Start(_, true) => {}
+        }
+    }
+    Some(unescape(text.trim()))
+}
+
+/// Whether an element with the given local name appears anywhere in the body.
+pub fn has_element(body: &str, name: &str) -> bool {
+    tokenize(body)
+        .unwrap_or_default()
+        .iter()
+        .any(|t| matches!(t, Token::Start(n, _) if local_name(n) == name))
+}
+
+/// Whether the body is a single, properly nested XML element.
+pub fn is_well_formed(body: &str) -> bool {
+    let tokens = match tokenize(body) {
+        Some(tokens) => tokens,
+        None => return false,
+    };
+    let mut stack = vec![];
+    let mut roots = 0;
+    for token in tokens {
+        match token {
+            Token::Start("", _) => return false,
+            Token::Start(name, self_closing) => {
+                if stack.is_empty() {
+                    roots += 1;
+                }
+                if !self_closing {
+                    stack.push(name);
+                }
+            }
+            Token::End(name) => {
+                if stack.pop() != Some(name) {
+                    return false;
+                }
+            }
+            Token::Text(text) => {
+                if stack.is_empty() && !text.trim().is_empty() {
+                    return false;
+                }
+            }
+        }
+    }
+    stack.is_empty() && roots == 1
+}
+
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// A property a PROPPATCH sets or removes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    /// The namespace URI, empty for a property in none.
+    pub namespace: String,
+    pub name: String,
+    /// The text to set, `None` to remove the property.
+    pub value: Option<String>,
+}
+
+/// The property updates of a PROPPATCH body in document order: a value for
+/// each property under `set`, `None` for each under `remove`. `None` when a
+/// property has an unbound prefix or a name that can't be written back.
+pub fn property_updates(body: &str) -> Option<Vec<Update>> {
+    let mut updates = vec![];
+    // local name and namespace declarations of each open element
+    let mut stack: Vec<(&str, Vec<(&str, String)>)> = vec![];
+    // the property being read and the depth it was opened at
+    let mut current: Option<(Update, usize)> = None;
+    for (token, attributes) in tokenize_with_attributes(body)? {
+        match token {
+            Token::Start(name, self_closing) => {
+                let declared = self::attributes(attributes)
+                    .into_iter()
+                    .filter_map(|(attribute, uri)| match attribute {
+                        "xmlns" => Some(("", uri)),
+                        _ => attribute.strip_prefix("xmlns:").map(|prefix| (prefix, uri)),
+                    })
+                    .collect::<Vec<_>>();
+                let local = local_name(name);
+                // a child of `prop`, itself in `set` or `remove`
+                let parents = stack
+                    .len()
+                    .checked_sub(2)
+                    .map(|i| (stack[i].0, stack[i + 1].0));
+                let value = match parents {
+                    _ if current.is_some() => None,
+                    Some(("set", "prop")) => Some(Some(String::new())),
+                    Some(("remove", "prop")) => Some(None),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    let prefix = name.rsplit_once(':').map_or("", |(prefix, _)| prefix);
+                    let namespace = std::iter::once(&declared)
+                        .chain(stack.iter().rev().map(|(_, declared)| declared))
+                        .flatten()
+                        .find(|(declared, _)| *declared == prefix)
+                        .map(|(_, uri)| uri.clone());
+                    let namespace = match namespace {
+                        Some(namespace) => namespace,
+                        None if prefix.is_empty() => String::new(),
+                        None => return None,
+                    };
+                    if !is_name(local) {
+                        return None;
+                    }
+                    let update = Update {
+                        namespace,
+                        name: local.to_string(),
+                        value,
+                    };
+                    if self_closing {
+                        updates.push(update);
+                        continue;
+                    }
+                    current = Some((update, stack.len()));
+                }
+                if !self_closing {
+                    stack.push((local, declared));
+                }
+            }
+            Token::End(_) => {
+                stack.pop();
+                if current
+                    .as_ref()
+                    .is_some_and(|(_, depth)| stack.len() == *depth)
+                {
+                    let (mut update, _) = current.take()?;
+                    update.value = update.value.map(|v| unescape(v.trim()));
+                    updates.push(update);
+                }
+            }
+            Token::Text(text) => {
+                if let Some((update, _)) = &mut current {
+                    if let Some(value) = &mut update.value {
+                        value.push_str(text);
+                    }
+                }
+            }
+        }
+    }
+    Some(updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn well_formed_documents() {
+        assert!(is_well_formed(
+            r#"<?xml version="1.0"?><D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind>"#
+        ));
+        assert!(is_well_formed("<a>\n  <!-- note --><b>text</b>\n</a>\n"));
+        assert!(is_well_formed("<a><![CDATA[<b>]]></a>"));
+        assert!(is_well_formed("<a/>"));
+    }
+
+    #[test]
+    fn malformed_documents() {
+        assert!(!is_well_formed(""));
+        assert!(!is_well_formed("<a>"));
+        assert!(!is_well_formed("<a><b></a></b>"));
+        assert!(!is_well_formed("<a/><b/>"));
+        assert!(!is_well_formed("text<a/>"));
+        assert!(!is_well_formed("<a><!-- open</a>"));
+        assert!(!is_well_formed("< >"));
+    }
+
+    fn update(namespace: &str, name: &str, value: Option<&str>) -> Update {
+        Update {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            value: value.map(String::from),
+        }
+    }
+
+    #[test]
+    fn property_updates_in_order() {
+        let body = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:z"><D:set><D:prop><Z:color> red &amp; blue </Z:color><Z:empty/></D:prop></D:set><D:remove><D:prop><Z:size/></D:prop></D:remove></D:propertyupdate>"#;
+        assert_eq!(
+            property_updates(body),
+            Some(vec![
+                update("urn:z", "color", Some("red & blue")),
+                update("urn:z", "empty", Some("")),
+                update("urn:z", "size", None),
+            ])
+        );
+    }
+
+    #[test]
+    fn property_namespaces_resolve_by_scope() {
+        let body = r#"<propertyupdate xmlns="DAV:"><set><prop xmlns:W='urn:schemas-microsoft-com:'><W:Win32FileAttributes>00000020</W:Win32FileAttributes><getcontenttype>text/plain</getcontenttype><note xmlns="">hi</note><tags xmlns="urn:cloudflare-webdav">a</tags></prop></set></propertyupdate>"#;
+        assert_eq!(
+            property_updates(body),
+            Some(vec![
+                update(
+                    "urn:schemas-microsoft-com:",
+                    "Win32FileAttributes",
+                    Some("00000020")
+                ),
+                update("DAV:", "getcontenttype", Some("text/plain")),
+                update("", "note", Some("hi")),
+                update("urn:cloudflare-webdav", "tags", Some("a")),
+            ])
+        );
+    }
+
+    #[test]
+    fn unbound_prefixes_and_bad_names_are_refused() {
+        let unbound = r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop><Z:color>red</Z:color></D:prop></D:set></D:propertyupdate>"#;
+        assert_eq!(property_updates(unbound), None);
+        let bad_name = r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop><a"b/></D:prop></D:set></D:propertyupdate>"#;
+        assert_eq!(property_updates(bad_name), None);
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        let text = r#"<a href="x">Tom & Jerry's</a>"#;
+        assert_eq!(
+            escape(text),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+        assert_eq!(unescape(&escape(text)), text);
+        assert_eq!(unescape("&amp;lt;"), "&lt;");
+    }
+
+    #[test]
+    fn element_text_by_local_name() {
+        let body = r#"<D:lockinfo xmlns:D="DAV:"><D:owner> <D:href>me &amp; you</D:href> </D:owner></D:lockinfo>"#;
+        assert_eq!(element_text(body, "owner").as_deref(), Some("me & you"));
+        assert_eq!(element_text("<a><b/></a>", "b").as_deref(), Some(""));
+        assert_eq!(element_text("<a/>", "b"), None);
+    }
+}
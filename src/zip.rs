@@ -0,0 +1,426 @@
+//! Streaming ZIP archives of a collection. Entries are stored uncompressed so
+//! each object body can be passed through as R2 streams it: a local header,
+//! the body, then a data descriptor with the CRC-32 computed on the way. The
+//! central directory follows the last entry. Folders get entries of their
+//! own, so extracting reproduces the layout, empty folders included. Without
+//! ZIP64 an archive holds at most 65535 entries and 4 GiB.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use futures_util::stream::{self, Stream, StreamExt};
+use worker::{Bucket, ByteStream, Result};
+
+use crate::path::decode;
+use crate::retry::retry;
+
+/// Bit 3: sizes and CRC follow the data; bit 11: names are UTF-8.
+const FLAGS: u16 = 0x0808;
+/// Version 2.0, the first to know directories and data descriptors.
+const VERSION: u16 = 20;
+
+const LOCAL_HEADER_LEN: u64 = 30;
+const DESCRIPTOR_LEN: u64 = 16;
+const CENTRAL_HEADER_LEN: u64 = 46;
+const END_LEN: u64 = 22;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+/// An object to archive: its key, the name it gets in the archive, its size
+/// as listed, and when it was uploaded. A name ending in `/` is a folder,
+/// which has no body to fetch.
+pub struct Entry {
+    pub key: String,
+    pub name: String,
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+}
+
+/// The name a key gets in the archive, given relative to the archived
+/// collection: each segment decoded for people to read, except that escaped
+/// separators stay escaped, so `a%2Fb` doesn't turn into a folder `a`. `None`
+/// for a key with an empty, `.` or `..` segment, which an extractor could be
+/// made to write outside its target folder with.
+pub fn entry_name(relative: &str) -> Option<String> {
+    let (path, folder) = match relative.strip_suffix('/') {
+        Some(path) => (path, true),
+        None => (relative, false),
+    };
+    let mut name = String::new();
+    for segment in path.split('/') {
+        let segment = decode(segment);
+        if matches!(segment.as_str(), "" | "." | "..") {
+            return None;
+        }
+        name.push_str(&segment.replace('/', "%2F").replace('\\', "%5C"));
+        name.push('/');
+    }
+    if !folder {
+        name.pop();
+    }
+    Some(name)
+}
+
+/// `entries` led by an entry for every folder on the way to them, dated like
+/// the first of its members, so the layout extracts as folders. Folder
+/// markers among `entries` are folded into those, as is each empty folder
+/// they keep, so every folder comes once and ahead of the files.
+pub fn with_folders(entries: Vec<Entry>) -> Vec<Entry> {
+    let mut folders = BTreeMap::new();
+    let mut files = vec![];
+    for entry in entries {
+        for (end, _) in entry.name.match_indices('/') {
+            folders
+                .entry(entry.name[..=end].to_string())
+                .or_insert(entry.modified);
+        }
+        if !entry.name.ends_with('/') {
+            files.push(entry);
+        }
+    }
+    folders
+        .into_iter()
+        .map(|(name, modified)| Entry {
+            key: String::new(),
+            name,
+            size: 0,
+            modified,
+        })
+        .chain(files)
+        .collect()
+}
+
+/// Whether `entries` fit an archive without ZIP64.
+pub fn fits(entries: &[Entry]) -> bool {
+    let size = entries
+        .iter()
+        .map(|e| {
+            LOCAL_HEADER_LEN
+                + DESCRIPTOR_LEN
+                + CENTRAL_HEADER_LEN
+                + 2 * e.name.len() as u64
+                + e.size
+        })
+        .sum::<u64>()
+        + END_LEN;
+    entries.len() <= u16::MAX as usize && size <= u32::MAX as u64
+}
+
+/// The archive of `entries`, fetched from `bucket` one at a time as the
+/// client reads, so only the current chunk is ever held in memory. Objects
+/// deleted since they were listed are left out.
+pub fn stream(bucket: Bucket, entries: Vec<Entry>) -> impl Stream<Item = Result<Vec<u8>>> {
+    let archive = Archive {
+        bucket,
+        entries: entries.into_iter(),
+        current: None,
+        offset: 0,
+        directory: vec![],
+        count: 0,
+        finished: false,
+    };
+    stream::unfold(Some(archive), |archive| async move {
+        let mut archive = archive?;
+        match archive.next_chunk().await {
+            Ok(Some(chunk)) => Some((Ok(chunk), Some(archive))),
+            Ok(None) => None,
+            // Nothing sensible can follow a failed entry.
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+struct Archive {
+    bucket: Bucket,
+    entries: std::vec::IntoIter<Entry>,
+    current: Option<Current>,
+    /// Bytes sent so far, which is where the next header starts.
+    offset: u64,
+    directory: Vec<u8>,
+    count: u16,
+    finished: bool,
+}
+
+/// The entry whose body is being sent.
+struct Current {
+    entry: Entry,
+    header_offset: u64,
+    body: Option<ByteStream>,
+    crc: u32,
+    size: u64,
+}
+
+impl Archive {
+    async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(mut current) = self.current.take() {
+                let chunk = match &mut current.body {
+                    Some(body) => body.next().await,
+                    None => None,
+                };
+                if let Some(chunk) = chunk {
+                    let chunk = chunk?;
+                    current.crc = update_crc(current.crc, &chunk);
+                    current.size += chunk.len() as u64;
+                    self.offset += chunk.len() as u64;
+                    self.current = Some(current);
+                    return Ok(Some(chunk));
+                }
+                return self.finish_entry(current).map(Some);
+            }
+            if let Some(entry) = self.entries.next() {
+                let body = if entry.name.ends_with('/') {
+                    None
+                } else {
+                    match retry(|| self.bucket.get(&entry.key).execute()).await? {
+                        Some(object) => Some(object.body().ok_or("Body is None")?.stream()?),
+                        None => continue,
+                    }
+                };
+                let header = local_header(&entry);
+                self.current = Some(Current {
+                    entry,
+                    header_offset: self.offset,
+                    body,
+                    crc: !0,
+                    size: 0,
+                });
+                self.offset += header.len() as u64;
+                return Ok(Some(header));
+            }
+            if self.finished {
+                return Ok(None);
+            }
+            self.finished = true;
+            return self.end_of_archive().map(Some);
+        }
+    }
+
+    /// The data descriptor of `current`, recording its central directory
+    /// header for the end.
+    fn finish_entry(&mut self, current: Current) -> Result<Vec<u8>> {
+        let crc = !current.crc;
+        let size = u32::try_from(current.size).map_err(|_| "ZIP entry too large")?;
+        let header_offset =
+            u32::try_from(current.header_offset).map_err(|_| "ZIP archive too large")?;
+        let mut descriptor = vec![];
+        put_u32(&mut descriptor, 0x0807_4b50);
+        put_u32(&mut descriptor, crc);
+        put_u32(&mut descriptor, size);
+        put_u32(&mut descriptor, size);
+        self.offset += descriptor.len() as u64;
+
+        let (time, date) = dos_time(&current.entry.modified);
+        let name = current.entry.name.as_bytes();
+        let directory = &mut self.directory;
+        put_u32(directory, 0x0201_4b50);
+        put_u16(directory, VERSION);
+        put_u16(directory, VERSION);
+        put_u16(directory, FLAGS);
+        put_u16(directory, 0);
+        put_u16(directory, time);
+        put_u16(directory, date);
+        put_u32(directory, crc);
+        put_u32(directory, size);
+        put_u32(directory, size);
+        put_u16(directory, name.len() as u16);
+        // extra field, comment, disk and internal attributes
+        directory.extend_from_slice(&[0; 8]);
+        // external attributes: the MS-DOS directory bit on folders
+        put_u32(
+            directory,
+            if current.entry.name.ends_with('/') {
+                0x10
+            } else {
+                0
+            },
+        );
+        put_u32(directory, header_offset);
+        directory.extend_from_slice(name);
+        self.count += 1;
+        Ok(descriptor)
+    }
+
+    /// The central directory and its end record.
+    fn end_of_archive(&mut self) -> Result<Vec<u8>> {
+        let offset = u32::try_from(self.offset).map_err(|_| "ZIP archive too large")?;
+        let mut end = std::mem::take(&mut self.directory);
+        let directory_len = end.len() as u32;
+        put_u32(&mut end, 0x0605_4b50);
+        put_u16(&mut end, 0);
+        put_u16(&mut end, 0);
+        put_u16(&mut end, self.count);
+        put_u16(&mut end, self.count);
+        put_u32(&mut end, directory_len);
+        put_u32(&mut end, offset);
+        put_u16(&mut end, 0);
+        Ok(end)
+    }
+}
+
+/// Folds `bytes` into a running CRC-32, which starts at `!0` and is
+/// inverted once the last byte is in.
+fn update_crc(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, byte| {
+        CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// The local file header that opens `entry`.
+fn local_header(entry: &Entry) -> Vec<u8> {
+    let (time, date) = dos_time(&entry.modified);
+    let mut header = vec![];
+    put_u32(&mut header, 0x0403_4b50);
+    put_u16(&mut header, VERSION);
+    put_u16(&mut header, FLAGS);
+    put_u16(&mut header, 0); // stored
+    put_u16(&mut header, time);
+    put_u16(&mut header, date);
+    // CRC and sizes are in the data descriptor.
+    header.extend_from_slice(&[0; 12]);
+    put_u16(&mut header, entry.name.len() as u16);
+    put_u16(&mut header, 0);
+    header.extend_from_slice(entry.name.as_bytes());
+    header
+}
+
+/// MS-DOS time and date, which can't go back before 1980.
+fn dos_time(time: &DateTime<Utc>) -> (u16, u16) {
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let dos_time = ((time.hour() << 11) | (time.minute() << 5) | (time.second() / 2)) as u16;
+    let dos_date = (((time.year() as u32 - 1980) << 9) | (time.month() << 5) | time.day()) as u16;
+    (dos_time, dos_date)
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry(name: &str, size: u64) -> Entry {
+        Entry {
+            key: name.to_string(),
+            name: name.to_string(),
+            size,
+            modified: Utc.with_ymd_and_hms(2024, 3, 15, 13, 45, 30).unwrap(),
+        }
+    }
+
+    #[test]
+    fn entry_names_are_decoded_per_segment() {
+        assert_eq!(
+            entry_name("docs/r%C3%A9sum%C3%A9.pdf").as_deref(),
+            Some("docs/résumé.pdf")
+        );
+        assert_eq!(entry_name("docs/old/").as_deref(), Some("docs/old/"));
+        assert_eq!(entry_name("a%2Fb.txt").as_deref(), Some("a%2Fb.txt"));
+        assert_eq!(entry_name("a%5Cb.txt").as_deref(), Some("a%5Cb.txt"));
+    }
+
+    #[test]
+    fn entry_names_never_leave_the_folder() {
+        assert_eq!(entry_name("../etc/passwd"), None);
+        assert_eq!(entry_name("a/%2E%2E/%2e%2e/b"), None);
+        assert_eq!(entry_name("a/./b"), None);
+        assert_eq!(entry_name("/etc/passwd"), None);
+        assert_eq!(entry_name("a//b"), None);
+        assert_eq!(entry_name("..%2Fb"), Some(String::from("..%2Fb")));
+    }
+
+    #[test]
+    fn crc_matches_the_check_value() {
+        assert_eq!(!update_crc(!0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(!update_crc(!0, b""), 0);
+    }
+
+    #[test]
+    fn crc_can_be_fed_in_chunks() {
+        let whole = update_crc(!0, b"123456789");
+        assert_eq!(update_crc(update_crc(!0, b"1234"), b"56789"), whole);
+    }
+
+    #[test]
+    fn dos_time_packs_fields() {
+        let (time, date) = dos_time(&Utc.with_ymd_and_hms(2024, 3, 15, 13, 45, 30).unwrap());
+        assert_eq!(time, (13 << 11) | (45 << 5) | 15);
+        assert_eq!(date, (44 << 9) | (3 << 5) | 15);
+    }
+
+    #[test]
+    fn dos_time_clamps_before_1980() {
+        let epoch = Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(dos_time(&epoch), (0, (1 << 5) | 1));
+    }
+
+    #[test]
+    fn local_header_layout() {
+        let header = local_header(&entry("docs/a.txt", 5));
+        assert_eq!(header.len() as u64, LOCAL_HEADER_LEN + 10);
+        assert_eq!(header[..4], 0x0403_4b50u32.to_le_bytes());
+        assert_eq!(header[4..6], VERSION.to_le_bytes());
+        assert_eq!(header[6..8], FLAGS.to_le_bytes());
+        assert_eq!(header[8..10], [0, 0]);
+        assert_eq!(header[14..26], [0; 12]);
+        assert_eq!(header[26..28], 10u16.to_le_bytes());
+        assert_eq!(header[28..30], [0, 0]);
+        assert_eq!(&header[30..], b"docs/a.txt");
+    }
+
+    #[test]
+    fn fits_within_zip32_limits() {
+        assert!(fits(&[]));
+        assert!(fits(&[entry("a", 1024), entry("b/", 0)]));
+        assert!(!fits(&[entry("big", u32::MAX as u64)]));
+        let many = (0..=u16::MAX as usize)
+            .map(|i| entry(&i.to_string(), 0))
+            .collect::<Vec<_>>();
+        assert!(!fits(&many));
+    }
+
+    #[test]
+    fn folders_on_the_way_come_first_and_once() {
+        let entries = with_folders(vec![
+            entry("b/c/d.txt", 3),
+            entry("a.txt", 1),
+            entry("b/", 0),
+            entry("b/c/e.txt", 2),
+            entry("empty/", 0),
+        ]);
+        let names = entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["b/", "b/c/", "empty/", "b/c/d.txt", "a.txt", "b/c/e.txt"]
+        );
+        assert!(entries[..3].iter().all(|e| e.key.is_empty() && e.size == 0));
+    }
+}